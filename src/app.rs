@@ -0,0 +1,387 @@
+//! Safe wrappers for SPDK application framework (`spdk_app_*` API).
+use std::{
+    ffi::CString,
+    mem::{size_of, zeroed},
+    os::raw::{c_char, c_int, c_void},
+    ptr::null_mut,
+};
+
+use snafu::Snafu;
+
+use crate::{
+    ffihelper::IntoCString,
+    libspdk::{
+        size_t,
+        spdk_app_fini,
+        spdk_app_opts,
+        spdk_app_opts_init,
+        spdk_app_parse_args,
+        spdk_app_start,
+        spdk_app_start_shutdown,
+        spdk_app_stop,
+        spdk_log_level,
+        SPDK_APP_PARSE_ARGS_HELP,
+        SPDK_APP_PARSE_ARGS_SUCCESS,
+        SPDK_LOG_DEBUG,
+        SPDK_LOG_DISABLED,
+        SPDK_LOG_ERROR,
+        SPDK_LOG_INFO,
+        SPDK_LOG_NOTICE,
+        SPDK_LOG_WARN,
+    },
+};
+
+/// SPDK application errors.
+#[derive(Debug, Snafu, Clone)]
+pub enum AppError {
+    #[snafu(display("Failed to parse SPDK application arguments"))]
+    ParseArgs {},
+    #[snafu(display("SPDK application usage was requested"))]
+    Usage {},
+    #[snafu(display("SPDK application name must be set"))]
+    NameMissing {},
+    #[snafu(display("SPDK application has already been started"))]
+    AlreadyStarted {},
+    #[snafu(display("Failed to start SPDK application: error code {code}"))]
+    StartFailed { code: i32 },
+    #[snafu(display("SPDK application stopped with error code {code}"))]
+    Stopped { code: i32 },
+}
+
+/// SPDK log levels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogLevel {
+    Disabled,
+    Error,
+    Warn,
+    Notice,
+    Info,
+    Debug,
+}
+
+impl From<LogLevel> for spdk_log_level {
+    fn from(l: LogLevel) -> Self {
+        match l {
+            LogLevel::Disabled => SPDK_LOG_DISABLED,
+            LogLevel::Error => SPDK_LOG_ERROR,
+            LogLevel::Warn => SPDK_LOG_WARN,
+            LogLevel::Notice => SPDK_LOG_NOTICE,
+            LogLevel::Info => SPDK_LOG_INFO,
+            LogLevel::Debug => SPDK_LOG_DEBUG,
+        }
+    }
+}
+
+/// SPDK application instance.
+///
+/// An application is created by `AppBuilder`, and started with
+/// `SpdkApp::start()`, which blocks until the application is stopped.
+/// When a started application is dropped, `spdk_app_fini` is called.
+pub struct SpdkApp {
+    /// SPDK application options.
+    opts: spdk_app_opts,
+    /// Strings referred by `opts`: they must outlive the application.
+    _strings: Vec<CString>,
+    /// Command line arguments given to `spdk_app_parse_args`.
+    /// SPDK may store pointers to them in `opts`.
+    _args: Vec<CString>,
+    /// Set when `spdk_app_start` has been called.
+    started: bool,
+}
+
+impl Drop for SpdkApp {
+    fn drop(&mut self) {
+        if self.started {
+            unsafe { spdk_app_fini() };
+        }
+    }
+}
+
+impl SpdkApp {
+    /// Starts the SPDK application framework, and calls the given closure
+    /// on the primary reactor once it is up and running.
+    /// This function blocks until the application is stopped with
+    /// `SpdkApp::stop()` or a signal.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_fn`: Closure called on the primary reactor after start.
+    pub fn start<F>(&mut self, start_fn: F) -> Result<(), AppError>
+    where
+        F: FnOnce() + 'static,
+    {
+        if self.started {
+            return Err(AppError::AlreadyStarted {});
+        }
+        self.started = true;
+
+        let mut ctx = StartContext {
+            start_fn: Some(start_fn),
+            called: false,
+        };
+
+        let code = unsafe {
+            spdk_app_start(
+                &mut self.opts,
+                Some(inner_app_start::<F>),
+                &mut ctx as *mut StartContext<F> as *mut c_void,
+            )
+        };
+
+        match (code, ctx.called) {
+            (0, _) => Ok(()),
+            (code, false) => Err(AppError::StartFailed {
+                code,
+            }),
+            (code, true) => Err(AppError::Stopped {
+                code,
+            }),
+        }
+    }
+
+    /// Stops a running SPDK application. `SpdkApp::start()` returns after
+    /// the framework has been shut down.
+    ///
+    /// # Arguments
+    ///
+    /// * `rc`: Return code passed to the application; non-zero means error.
+    pub fn stop(rc: i32) {
+        unsafe { spdk_app_stop(rc) };
+    }
+
+    /// Initiates a graceful shutdown of a running SPDK application, in the
+    /// same way it happens upon SIGINT.
+    pub fn shutdown() {
+        unsafe { spdk_app_start_shutdown() };
+    }
+}
+
+/// Context for the application start callback.
+struct StartContext<F> {
+    start_fn: Option<F>,
+    called: bool,
+}
+
+/// Called by SPDK on the primary reactor when the application has started.
+///
+/// # Arguments
+///
+/// * `ctx`: Pointer to `StartContext<F>`.
+unsafe extern "C" fn inner_app_start<F>(ctx: *mut c_void)
+where
+    F: FnOnce() + 'static,
+{
+    let ctx = &mut *(ctx as *mut StartContext<F>);
+    ctx.called = true;
+    if let Some(f) = ctx.start_fn.take() {
+        f();
+    }
+}
+
+/// Dummy argument parser: application-specific arguments are not supported.
+unsafe extern "C" fn inner_app_parse_arg(
+    _ch: c_int,
+    _arg: *mut c_char,
+) -> c_int {
+    0
+}
+
+/// Dummy usage printer: application-specific arguments are not supported.
+unsafe extern "C" fn inner_app_usage() {}
+
+/// Builder for `SpdkApp`.
+#[derive(Default)]
+pub struct AppBuilder {
+    name: Option<String>,
+    reactor_mask: Option<String>,
+    mem_size: Option<i32>,
+    hugedir: Option<String>,
+    hugepage_single_segments: bool,
+    unlink_hugepage: bool,
+    no_huge: bool,
+    rpc_addr: Option<String>,
+    json_config_file: Option<String>,
+    json_config_ignore_errors: bool,
+    log_level: Option<LogLevel>,
+    args: Option<Vec<String>>,
+}
+
+impl AppBuilder {
+    /// Creates a new `AppBuilder` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the application name.
+    /// This parameter is mandatory.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    /// Sets the reactor CPU mask, e.g. `0x3` or `[0,1]`.
+    pub fn with_reactor_mask(mut self, mask: &str) -> Self {
+        self.reactor_mask = Some(String::from(mask));
+        self
+    }
+
+    /// Sets the amount of memory to allocate for the application, in MiB.
+    pub fn with_mem_size(mut self, mem_size: i32) -> Self {
+        self.mem_size = Some(mem_size);
+        self
+    }
+
+    /// Sets the hugetlbfs mount point to allocate memory from.
+    pub fn with_hugedir(mut self, hugedir: &str) -> Self {
+        self.hugedir = Some(String::from(hugedir));
+        self
+    }
+
+    /// Forces memory to be allocated in a single hugepage segment.
+    pub fn with_hugepage_single_segments(mut self, val: bool) -> Self {
+        self.hugepage_single_segments = val;
+        self
+    }
+
+    /// Unlinks hugepage files after initialization.
+    pub fn with_unlink_hugepage(mut self, val: bool) -> Self {
+        self.unlink_hugepage = val;
+        self
+    }
+
+    /// Disables hugepages, and allocates memory from anonymous memory.
+    pub fn with_no_huge(mut self, val: bool) -> Self {
+        self.no_huge = val;
+        self
+    }
+
+    /// Sets the RPC listen address (e.g. a UNIX domain socket path).
+    pub fn with_rpc_addr(mut self, addr: &str) -> Self {
+        self.rpc_addr = Some(String::from(addr));
+        self
+    }
+
+    /// Sets the JSON configuration file to load on startup.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Path to the configuration file.
+    /// * `ignore_errors`: If true, errors in the configuration do not stop the
+    ///   application from starting.
+    pub fn with_json_config_file(
+        mut self,
+        path: &str,
+        ignore_errors: bool,
+    ) -> Self {
+        self.json_config_file = Some(String::from(path));
+        self.json_config_ignore_errors = ignore_errors;
+        self
+    }
+
+    /// Sets the level of SPDK log messages printed to stderr.
+    pub fn with_log_level(mut self, level: LogLevel) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Sets command line arguments to be parsed by SPDK. Options given on
+    /// the command line override the ones set by the builder.
+    /// The first argument is the program name.
+    pub fn with_args<I>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.args = Some(args.into_iter().collect());
+        self
+    }
+
+    /// Consumes an `AppBuilder` instance and produces a new `SpdkApp`
+    /// instance, ready to be started. The application name must be set.
+    pub fn build(self) -> Result<SpdkApp, AppError> {
+        let name = self.name.ok_or(AppError::NameMissing {})?;
+
+        let mut strings = Vec::new();
+        let mut keep = |s: String| -> *const c_char {
+            let s = s.into_cstring();
+            let p = s.as_ptr();
+            strings.push(s);
+            p
+        };
+
+        let mut opts: spdk_app_opts = unsafe { zeroed() };
+        unsafe {
+            spdk_app_opts_init(&mut opts, size_of::<spdk_app_opts>() as size_t)
+        };
+
+        opts.name = keep(name);
+
+        if let Some(s) = self.reactor_mask {
+            opts.reactor_mask = keep(s);
+        }
+
+        if let Some(s) = self.mem_size {
+            opts.mem_size = s;
+        }
+
+        if let Some(s) = self.hugedir {
+            opts.hugedir = keep(s);
+        }
+
+        opts.hugepage_single_segments = self.hugepage_single_segments;
+        opts.unlink_hugepage = self.unlink_hugepage;
+        opts.no_huge = self.no_huge;
+
+        if let Some(s) = self.rpc_addr {
+            opts.rpc_addr = keep(s);
+        }
+
+        if let Some(s) = self.json_config_file {
+            opts.json_config_file = keep(s);
+            opts.json_config_ignore_errors = self.json_config_ignore_errors;
+        }
+
+        if let Some(l) = self.log_level {
+            opts.print_level = l.into();
+        }
+
+        let args = self
+            .args
+            .unwrap_or_default()
+            .into_iter()
+            .map(IntoCString::into_cstring)
+            .collect::<Vec<CString>>();
+
+        if !args.is_empty() {
+            let mut c_args = args
+                .iter()
+                .map(|a| a.as_ptr() as *mut c_char)
+                .collect::<Vec<*mut c_char>>();
+            c_args.push(null_mut());
+
+            let rc = unsafe {
+                spdk_app_parse_args(
+                    args.len() as c_int,
+                    c_args.as_mut_ptr(),
+                    &mut opts,
+                    null_mut(),
+                    null_mut(),
+                    Some(inner_app_parse_arg),
+                    Some(inner_app_usage),
+                )
+            };
+
+            match rc {
+                SPDK_APP_PARSE_ARGS_SUCCESS => {}
+                SPDK_APP_PARSE_ARGS_HELP => return Err(AppError::Usage {}),
+                _ => return Err(AppError::ParseArgs {}),
+            }
+        }
+
+        Ok(SpdkApp {
+            opts,
+            _strings: strings,
+            _args: args,
+            started: false,
+        })
+    }
+}
@@ -1,14 +1,16 @@
 //! Wrappers for SPDK `spdk_bdev` structure and the related API.
 use std::{
+    any::TypeId,
     ffi::CString,
     fmt::{Debug, Formatter},
     marker::{PhantomData, PhantomPinned},
     os::raw::c_void,
     pin::Pin,
-    ptr::{null_mut, NonNull},
+    ptr::{addr_of, null_mut, NonNull},
 };
 
 use nix::errno::Errno;
+use parking_lot::Mutex;
 
 use crate::{
     ffihelper::{
@@ -38,6 +40,7 @@ use crate::{
     BdevIo,
     BdevModule,
     BdevOps,
+    DifType,
     IoChannel,
     IoDevice,
     IoType,
@@ -259,6 +262,62 @@ where
         self.as_inner_ref().required_alignment
     }
 
+    /// Returns the metadata size per block in bytes, or zero if the Bdev
+    /// has no metadata.
+    pub fn md_len(&self) -> u32 {
+        self.as_inner_ref().md_len
+    }
+
+    /// Determines if metadata is interleaved with block data.
+    pub fn is_md_interleaved(&self) -> bool {
+        self.md_len() != 0 && self.as_inner_ref().md_interleave
+    }
+
+    /// Determines if metadata is transferred in a separate buffer.
+    pub fn is_md_separate(&self) -> bool {
+        self.md_len() != 0 && !self.as_inner_ref().md_interleave
+    }
+
+    /// Returns the protection information type of the Bdev.
+    pub fn dif_type(&self) -> DifType {
+        self.as_inner_ref().dif_type.into()
+    }
+
+    /// Determines if protection information is at the head of metadata.
+    pub fn is_dif_head_of_md(&self) -> bool {
+        self.as_inner_ref().dif_is_head_of_md
+    }
+
+    /// Returns the DIF check flags of the Bdev (see `dif_check_flags`).
+    pub fn dif_check_flags(&self) -> u32 {
+        self.as_inner_ref().dif_check_flags
+    }
+
+    /// Determines if the Bdev is a zoned block device.
+    pub fn is_zoned(&self) -> bool {
+        self.as_inner_ref().zoned
+    }
+
+    /// Returns the zone size in blocks, or zero for non-zoned Bdevs.
+    pub fn zone_size(&self) -> u64 {
+        self.as_inner_ref().zone_size
+    }
+
+    /// Returns the maximum number of open zones, or zero if unlimited.
+    pub fn max_open_zones(&self) -> u32 {
+        self.as_inner_ref().max_open_zones
+    }
+
+    /// Returns the maximum number of active zones, or zero if unlimited.
+    pub fn max_active_zones(&self) -> u32 {
+        self.as_inner_ref().max_active_zones
+    }
+
+    /// Returns the maximum size of a zone append I/O, in blocks.
+    pub fn max_zone_append_size(&self) -> u32 {
+        self.as_inner_ref().max_zone_append_size
+    }
+
     /// Returns true if this Bdev is claimed by some other component.
     pub fn is_claimed(&self) -> bool {
         unsafe {
@@ -317,6 +376,28 @@ where
         }
     }
 
+    /// Determines if this Bdev has been created by `BdevBuilder` with
+    /// the given data type.
+    pub(crate) fn has_data_type<T>(&self) -> bool
+    where
+        T: 'static,
+    {
+        let b = self.as_inner_ref();
+
+        // A Bdev made by `BdevBuilder` is the first field of its container,
+        // which is also its context, and its function table is another
+        // field of the container. Only then the container can be read.
+        if b.ctxt as *const spdk_bdev != self.as_inner_ptr() {
+            return false;
+        }
+
+        let c = b.ctxt as *const Container<()>;
+        unsafe {
+            b.fn_table == addr_of!((*c).fn_table)
+                && (*c).data_type == Some(TypeId::of::<T>())
+        }
+    }
+
     /// Returns a pointer to the underlying `spdk_bdev` structure.
     pub(crate) fn as_inner_ptr(&self) -> *mut spdk_bdev {
         self.inner.as_ptr()
@@ -398,6 +479,17 @@ where
     }
 }
 
+/// Driver-specific statistics of the I/O channels of a Bdev, collected by
+/// `BdevOps::channel_stat` for `BdevOps::dump_device_stat_json`.
+#[derive(Default)]
+pub(crate) struct ChannelStats {
+    /// Statistics of the last completed collection, one entry per channel,
+    /// or `None` if no collection has completed yet.
+    pub(crate) latest: Option<Vec<serde_json::Value>>,
+    /// Statistics of the collection in progress, if any.
+    pub(crate) pending: Option<Vec<serde_json::Value>>,
+}
+
 /// Container for the data associated with a `Bdev` instance.
 /// This container stores the `spdk_bdev` structure itself,
 /// its associated function table and user-defined data structure provided upon
@@ -418,6 +510,12 @@ where
     pub(crate) bdev: spdk_bdev,
     /// TODO
     pub(crate) fn_table: spdk_bdev_fn_table,
+    /// Type of the Bdev data. It precedes the data so that its offset does
+    /// not depend on the data type. `None` if the builder has not recorded
+    /// it.
+    pub(crate) data_type: Option<TypeId>,
+    /// Snapshot of driver-specific statistics of the I/O channels.
+    pub(crate) channel_stats: Mutex<ChannelStats>,
     /// TODO
     pub(crate) data: BdevData,
     /// Prevent auto-Unpin.
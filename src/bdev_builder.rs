@@ -1,27 +1,46 @@
-use std::{ffi::CString, mem::zeroed, os::raw::c_void, ptr::null_mut};
+use std::{
+    any::TypeId,
+    ffi::CString,
+    mem::zeroed,
+    os::raw::c_void,
+    pin::Pin,
+    ptr::null_mut,
+};
 
 use crate::{
     bdev::Container,
+    bdev_module::add_module_bdev_data_type,
+    dif::dif_check_flags,
     ffihelper::IntoCString,
     libspdk::{
         spdk_bdev,
         spdk_bdev_fn_table,
         spdk_bdev_io,
         spdk_bdev_io_type,
+        spdk_for_each_channel,
+        spdk_for_each_channel_continue,
         spdk_get_io_channel,
         spdk_io_channel,
+        spdk_io_channel_iter,
+        spdk_io_channel_iter_get_ctx,
         spdk_json_write_ctx,
+        spdk_memory_domain,
         SPDK_BDEV_RESET_IO_DRAIN_RECOMMENDED_VALUE,
     },
     Bdev,
+    BdevDesc,
     BdevIo,
     BdevModule,
     BdevOps,
+    DifType,
     IoChannel,
     IoDevice,
     IoType,
     JsonWriteContext,
+    SpdkError,
+    SpdkResult,
     Uuid,
+    WithBdevModuleCtx,
 };
 
 /// Builder for `Bdev` structure.
@@ -43,10 +62,37 @@ where
     blocklen: Option<u32>,
     blockcnt: Option<u64>,
     required_alignment: Option<u8>,
+    phys_blocklen: u32,
+    write_unit_size: u32,
+    split_on_write_unit: bool,
+    acwu: u16,
+    optimal_io_boundary: u32,
+    split_on_optimal_io_boundary: bool,
+    max_segment_size: u32,
+    max_num_segments: u32,
+    max_unmap: u32,
+    max_unmap_segments: u32,
+    max_write_zeroes: u32,
+    max_copy: u32,
+    max_rw_size: u32,
+    write_cache: bool,
+    md_len: u32,
+    md_interleave: bool,
+    dif_type: DifType,
+    dif_is_head_of_md: bool,
+    dif_check_flags: u32,
+    zoned: bool,
+    zone_size: u64,
+    max_zone_append_size: u32,
+    max_open_zones: u32,
+    max_active_zones: u32,
+    optimal_open_zones: u32,
     uuid: Option<Uuid>,
     module: &'m BdevModule,
     fn_table: Option<spdk_bdev_fn_table>,
     data: Option<BdevData>,
+    data_type: Option<TypeId>,
+    get_module_ctx: Option<unsafe extern "C" fn(*mut c_void) -> *mut c_void>,
 }
 
 impl<'m, BdevData> BdevBuilder<'m, BdevData>
@@ -65,13 +111,50 @@ where
             required_alignment: None,
             blocklen: None,
             blockcnt: None,
+            phys_blocklen: 0,
+            write_unit_size: 0,
+            split_on_write_unit: false,
+            acwu: 0,
+            optimal_io_boundary: 0,
+            split_on_optimal_io_boundary: false,
+            max_segment_size: 0,
+            max_num_segments: 0,
+            max_unmap: 0,
+            max_unmap_segments: 0,
+            max_write_zeroes: 0,
+            max_copy: 0,
+            max_rw_size: 0,
+            write_cache: false,
+            md_len: 0,
+            md_interleave: false,
+            dif_type: DifType::Disable,
+            dif_is_head_of_md: false,
+            dif_check_flags: 0,
+            zoned: false,
+            zone_size: 0,
+            max_zone_append_size: 0,
+            max_open_zones: 0,
+            max_active_zones: 0,
+            optimal_open_zones: 0,
             uuid: None,
             module: bdev_mod,
             fn_table: None,
             data: None,
+            data_type: None,
+            get_module_ctx: None,
         }
     }
 
+    /// Records the type of the Bdev data in the Bdev being created, so the
+    /// Bdev can later be downcast from an `UntypedBdev` to its typed form.
+    pub fn with_data_type(mut self) -> Self
+    where
+        BdevData: 'static,
+    {
+        self.data_type = Some(TypeId::of::<BdevData>());
+        self
+    }
+
     /// Sets the Bdev data object for the Bdev being created.
     /// This Bdev parameter is manadory.
     /// Creates a new `BdevBuilder` instance.
@@ -86,18 +169,32 @@ where
             io_type_supported: Some(inner_bdev_io_type_supported::<BdevData>),
             get_io_channel: Some(inner_bdev_get_io_channel::<BdevData>),
             dump_info_json: Some(inner_dump_info_json::<BdevData>),
-            write_config_json: None,
-            get_spin_time: None,
-            get_module_ctx: Some(inner_bdev_get_module_ctx::<BdevData>),
-            get_memory_domains: None,
-            dump_device_stat_json: None,
-            reset_device_stat: None,
-            accel_sequence_supported: None,
+            write_config_json: Some(inner_write_config_json::<BdevData>),
+            get_spin_time: Some(inner_get_spin_time::<BdevData>),
+            get_module_ctx: None,
+            get_memory_domains: Some(inner_get_memory_domains::<BdevData>),
+            dump_device_stat_json: Some(
+                inner_dump_device_stat_json::<BdevData>,
+            ),
+            reset_device_stat: Some(inner_reset_device_stat::<BdevData>),
+            accel_sequence_supported: Some(
+                inner_accel_sequence_supported::<BdevData>,
+            ),
         });
         self.data = Some(ctx);
         self
     }
 
+    /// Makes the Bdev being created provide its module-specific context
+    /// to SPDK `spdk_bdev_get_module_ctx` (see `WithBdevModuleCtx`).
+    pub fn with_module_ctx(mut self) -> Self
+    where
+        BdevData: WithBdevModuleCtx,
+    {
+        self.get_module_ctx = Some(inner_bdev_get_module_ctx::<BdevData>);
+        self
+    }
+
     /// Sets a UUID for the Bdev being created.
     ///
     /// # Arguments
@@ -163,8 +260,397 @@ where
         self
     }
 
+    /// Sets Bdev physical block length, if it differs from the logical
+    /// block length. Must be a multiple of the block length.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Physical block length in bytes.
+    pub fn with_physical_block_length(mut self, val: u32) -> Self {
+        self.phys_blocklen = val;
+        self
+    }
+
+    /// Sets Bdev write unit size: the number of blocks that the Bdev
+    /// requires to be written at once. Zero means one block.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Write unit size in blocks.
+    pub fn with_write_unit_size(mut self, val: u32) -> Self {
+        self.write_unit_size = val;
+        self
+    }
+
+    /// Makes the generic bdev layer split writes on write unit boundaries.
+    /// Requires a non-zero write unit size.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Split on write unit boundaries.
+    pub fn with_split_on_write_unit(mut self, val: bool) -> Self {
+        self.split_on_write_unit = val;
+        self
+    }
+
+    /// Sets Bdev atomic compare and write unit, in blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Atomic compare and write unit in blocks.
+    pub fn with_atomic_compare_write_unit(mut self, val: u16) -> Self {
+        self.acwu = val;
+        self
+    }
+
+    /// Sets Bdev optimal I/O boundary, in blocks. Zero means no boundary.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Optimal I/O boundary in blocks.
+    pub fn with_optimal_io_boundary(mut self, val: u32) -> Self {
+        self.optimal_io_boundary = val;
+        self
+    }
+
+    /// Makes the generic bdev layer split I/Os crossing the optimal I/O
+    /// boundary. Requires a non-zero optimal I/O boundary.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Split on optimal I/O boundaries.
+    pub fn with_split_on_optimal_io_boundary(mut self, val: bool) -> Self {
+        self.split_on_optimal_io_boundary = val;
+        self
+    }
+
+    /// Sets the maximum size of an I/O vector segment, in bytes.
+    /// Zero means no limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Maximum segment size in bytes.
+    pub fn with_max_segment_size(mut self, val: u32) -> Self {
+        self.max_segment_size = val;
+        self
+    }
+
+    /// Sets the maximum number of I/O vector segments of an I/O.
+    /// Zero means no limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Maximum number of segments.
+    pub fn with_max_num_segments(mut self, val: u32) -> Self {
+        self.max_num_segments = val;
+        self
+    }
+
+    /// Sets the maximum number of blocks in an unmap segment.
+    /// Zero means no limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Maximum unmap size in blocks.
+    pub fn with_max_unmap(mut self, val: u32) -> Self {
+        self.max_unmap = val;
+        self
+    }
+
+    /// Sets the maximum number of segments of an unmap I/O.
+    /// Zero means no limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Maximum number of unmap segments.
+    pub fn with_max_unmap_segments(mut self, val: u32) -> Self {
+        self.max_unmap_segments = val;
+        self
+    }
+
+    /// Sets the maximum number of blocks of a write zeroes I/O.
+    /// Zero means no limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Maximum write zeroes size in blocks.
+    pub fn with_max_write_zeroes(mut self, val: u32) -> Self {
+        self.max_write_zeroes = val;
+        self
+    }
+
+    /// Sets the maximum number of blocks of a copy I/O.
+    /// Zero means no limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Maximum copy size in blocks.
+    pub fn with_max_copy(mut self, val: u32) -> Self {
+        self.max_copy = val;
+        self
+    }
+
+    /// Sets the maximum number of blocks of a read or write I/O.
+    /// Zero means no limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Maximum read/write size in blocks.
+    pub fn with_max_rw_size(mut self, val: u32) -> Self {
+        self.max_rw_size = val;
+        self
+    }
+
+    /// Sets whether the Bdev has a volatile write cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: True if write cache is enabled.
+    pub fn with_write_cache(mut self, val: bool) -> Self {
+        self.write_cache = val;
+        self
+    }
+
+    /// Makes the Bdev use metadata transferred in a separate buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `md_len`: Metadata size per block in bytes.
+    pub fn with_separate_metadata(mut self, md_len: u32) -> Self {
+        self.md_len = md_len;
+        self.md_interleave = false;
+        self
+    }
+
+    /// Makes the Bdev use metadata interleaved with block data. In this
+    /// case, the block length includes the metadata size.
+    ///
+    /// # Arguments
+    ///
+    /// * `md_len`: Metadata size per block in bytes.
+    pub fn with_interleaved_metadata(mut self, md_len: u32) -> Self {
+        self.md_len = md_len;
+        self.md_interleave = true;
+        self
+    }
+
+    /// Enables protection information in the Bdev metadata.
+    /// Requires metadata of at least 8 bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `dif_type`: Protection information type.
+    /// * `head_of_md`: Protection information is at the head of metadata,
+    ///   rather than at its tail.
+    /// * `check_flags`: DIF checks to perform (see `dif_check_flags`).
+    pub fn with_dif(
+        mut self,
+        dif_type: DifType,
+        head_of_md: bool,
+        check_flags: u32,
+    ) -> Self {
+        self.dif_type = dif_type;
+        self.dif_is_head_of_md = head_of_md;
+        self.dif_check_flags = check_flags;
+        self
+    }
+
+    /// Makes the Bdev a zoned block device.
+    /// The block count must be a multiple of the zone size.
+    ///
+    /// # Arguments
+    ///
+    /// * `zone_size`: Zone size in blocks.
+    /// * `max_open_zones`: Maximum number of open zones; zero if unlimited.
+    /// * `max_active_zones`: Maximum number of active zones; zero if unlimited.
+    pub fn with_zoned(
+        mut self,
+        zone_size: u64,
+        max_open_zones: u32,
+        max_active_zones: u32,
+    ) -> Self {
+        self.zoned = true;
+        self.zone_size = zone_size;
+        self.max_open_zones = max_open_zones;
+        self.max_active_zones = max_active_zones;
+        self
+    }
+
+    /// Sets the maximum size of a zone append I/O, in blocks.
+    /// Zoned Bdevs only.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Maximum zone append size in blocks.
+    pub fn with_max_zone_append_size(mut self, val: u32) -> Self {
+        self.max_zone_append_size = val;
+        self
+    }
+
+    /// Sets the optimal number of open zones. Zoned Bdevs only.
+    ///
+    /// # Arguments
+    ///
+    /// * `val`: Optimal number of open zones.
+    pub fn with_optimal_open_zones(mut self, val: u32) -> Self {
+        self.optimal_open_zones = val;
+        self
+    }
+
+    /// Checks that all mandatory Bdev parameters are set, and that the
+    /// parameters do not conflict with each other.
+    pub fn validate(&self) -> SpdkResult<()> {
+        let missing = |param: &str| SpdkError::BdevParamMissing {
+            param: param.to_string(),
+        };
+
+        let name = self
+            .name
+            .as_ref()
+            .ok_or_else(|| missing("name"))?
+            .to_string_lossy();
+        self.product_name
+            .as_ref()
+            .ok_or_else(|| missing("product name"))?;
+        let blocklen = self.blocklen.ok_or_else(|| missing("block length"))?;
+        let blockcnt = self.blockcnt.ok_or_else(|| missing("block count"))?;
+        self.required_alignment
+            .ok_or_else(|| missing("required alignment"))?;
+        self.fn_table.as_ref().ok_or_else(|| missing("data"))?;
+
+        let invalid = |reason: String| SpdkError::BdevParamsInvalid {
+            name: name.to_string(),
+            reason,
+        };
+
+        if blocklen == 0 {
+            return Err(invalid("block length must not be zero".to_string()));
+        }
+
+        if blockcnt == 0 {
+            return Err(invalid("block count must not be zero".to_string()));
+        }
+
+        if self.phys_blocklen % blocklen != 0 {
+            return Err(invalid(format!(
+                "physical block length {} is not a multiple of \
+                block length {}",
+                self.phys_blocklen, blocklen
+            )));
+        }
+
+        if self.split_on_write_unit && self.write_unit_size == 0 {
+            return Err(invalid(
+                "splitting on write unit requires a write unit size"
+                    .to_string(),
+            ));
+        }
+
+        if self.split_on_optimal_io_boundary && self.optimal_io_boundary == 0 {
+            return Err(invalid(
+                "splitting on optimal I/O boundary requires \
+                an optimal I/O boundary"
+                    .to_string(),
+            ));
+        }
+
+        // SPDK splits on write unit using the optimal I/O boundary, so the
+        // two can only be combined if they are equal.
+        if self.split_on_write_unit
+            && self.split_on_optimal_io_boundary
+            && self.optimal_io_boundary != self.write_unit_size
+        {
+            return Err(invalid(format!(
+                "optimal I/O boundary {} conflicts with write unit size {}",
+                self.optimal_io_boundary, self.write_unit_size
+            )));
+        }
+
+        if self.md_interleave && self.md_len >= blocklen {
+            return Err(invalid(format!(
+                "interleaved metadata size {} must be less than \
+                block length {}",
+                self.md_len, blocklen
+            )));
+        }
+
+        if self.dif_type != DifType::Disable && self.md_len < 8 {
+            return Err(invalid(format!(
+                "protection information requires at least 8 bytes \
+                of metadata, got {}",
+                self.md_len
+            )));
+        }
+
+        if self.dif_type == DifType::Disable && self.dif_check_flags != 0 {
+            return Err(invalid(
+                "DIF check flags require protection information".to_string(),
+            ));
+        }
+
+        if self.dif_type == DifType::Type3
+            && self.dif_check_flags & dif_check_flags::REFTAG != 0
+        {
+            return Err(invalid(
+                "DIF type 3 does not support reference tag checks".to_string(),
+            ));
+        }
+
+        if self.zoned {
+            if self.zone_size == 0 || blockcnt % self.zone_size != 0 {
+                return Err(invalid(format!(
+                    "block count {} is not a multiple of zone size {}",
+                    blockcnt, self.zone_size
+                )));
+            }
+
+            if self.max_active_zones != 0
+                && self.max_open_zones > self.max_active_zones
+            {
+                return Err(invalid(format!(
+                    "max open zones {} exceeds max active zones {}",
+                    self.max_open_zones, self.max_active_zones
+                )));
+            }
+        } else if self.max_zone_append_size != 0 || self.optimal_open_zones != 0
+        {
+            return Err(invalid(
+                "zone parameters require a zoned Bdev".to_string(),
+            ));
+        }
+
+        if self.max_segment_size != 0 && self.max_segment_size < blocklen {
+            return Err(invalid(format!(
+                "max segment size {} is less than block length {}",
+                self.max_segment_size, blocklen
+            )));
+        }
+
+        Ok(())
+    }
+
     /// Consumes a `BdevBuilder` instance and produces a new `Bdev` instance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the Bdev parameters are invalid.
+    #[deprecated(note = "Use try_build() to get an error instead of a panic")]
     pub fn build(self) -> Bdev<BdevData> {
+        match self.try_build() {
+            Ok(bdev) => bdev,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Validates the parameters, consumes a `BdevBuilder` instance and
+    /// produces a new `Bdev` instance.
+    pub fn try_build(self) -> SpdkResult<Bdev<BdevData>> {
+        self.validate()?;
+
+        if let Some(data_type) = self.data_type {
+            add_module_bdev_data_type(self.module.as_ptr(), data_type);
+        }
+
         // Create a new container for the Bdev data, `spdk_bdev` itself and
         // the associated function table.
         // The context (pointer to the Container<> itself in our case) and
@@ -172,43 +658,38 @@ where
         let cont = Box::new(Container {
             bdev: spdk_bdev {
                 ctxt: null_mut::<c_void>(),
-                name: self.name.expect("Bdev name must be set").into_raw(),
+                name: self.name.unwrap().into_raw(),
                 aliases: unsafe { zeroed() },
-                product_name: self
-                    .product_name
-                    .expect("Bdev product name must be set")
-                    .into_raw(),
-                write_cache: Default::default(),
-                blocklen: self.blocklen.expect("Bdeb block length must be set"),
-                phys_blocklen: Default::default(),
-                blockcnt: self.blockcnt.expect("Bdeb block count must be set"),
-                split_on_write_unit: Default::default(),
-                write_unit_size: Default::default(),
-                acwu: Default::default(),
-                required_alignment: self
-                    .required_alignment
-                    .expect("Bdev required alignment must be set"),
-                split_on_optimal_io_boundary: Default::default(),
-                optimal_io_boundary: Default::default(),
-                max_segment_size: Default::default(),
-                max_num_segments: Default::default(),
-                max_unmap: Default::default(),
-                max_unmap_segments: Default::default(),
-                max_write_zeroes: Default::default(),
-                max_copy: Default::default(),
-                max_rw_size: Default::default(),
+                product_name: self.product_name.unwrap().into_raw(),
+                write_cache: self.write_cache as _,
+                blocklen: self.blocklen.unwrap(),
+                phys_blocklen: self.phys_blocklen,
+                blockcnt: self.blockcnt.unwrap(),
+                split_on_write_unit: self.split_on_write_unit,
+                write_unit_size: self.write_unit_size,
+                acwu: self.acwu,
+                required_alignment: self.required_alignment.unwrap(),
+                split_on_optimal_io_boundary: self.split_on_optimal_io_boundary,
+                optimal_io_boundary: self.optimal_io_boundary,
+                max_segment_size: self.max_segment_size,
+                max_num_segments: self.max_num_segments,
+                max_unmap: self.max_unmap,
+                max_unmap_segments: self.max_unmap_segments,
+                max_write_zeroes: self.max_write_zeroes,
+                max_copy: self.max_copy,
+                max_rw_size: self.max_rw_size,
                 uuid: self.uuid.unwrap_or_else(Uuid::generate).into_raw(),
-                md_len: Default::default(),
-                md_interleave: Default::default(),
-                dif_type: Default::default(),
-                dif_is_head_of_md: Default::default(),
-                dif_check_flags: Default::default(),
-                zoned: Default::default(),
-                zone_size: Default::default(),
-                max_zone_append_size: Default::default(),
-                max_open_zones: Default::default(),
-                max_active_zones: Default::default(),
-                optimal_open_zones: Default::default(),
+                md_len: self.md_len,
+                md_interleave: self.md_interleave,
+                dif_type: self.dif_type.into(),
+                dif_is_head_of_md: self.dif_is_head_of_md,
+                dif_check_flags: self.dif_check_flags,
+                zoned: self.zoned,
+                zone_size: self.zone_size,
+                max_zone_append_size: self.max_zone_append_size,
+                max_open_zones: self.max_open_zones,
+                max_active_zones: self.max_active_zones,
+                optimal_open_zones: self.optimal_open_zones,
                 media_events: Default::default(),
                 ctratt: unsafe { zeroed() },
                 reset_io_drain_timeout:
@@ -217,8 +698,13 @@ where
                 fn_table: null_mut::<spdk_bdev_fn_table>(),
                 internal: unsafe { zeroed() },
             },
-            fn_table: self.fn_table.expect("Bdev function table must be set"),
-            data: self.data.expect("Bdev data must be set"),
+            fn_table: spdk_bdev_fn_table {
+                get_module_ctx: self.get_module_ctx,
+                ..self.fn_table.unwrap()
+            },
+            data_type: self.data_type,
+            channel_stats: Default::default(),
+            data: self.data.unwrap(),
             _pin: Default::default(),
         });
 
@@ -232,7 +718,7 @@ where
         unsafe {
             (*pcont).bdev.fn_table = &(*pcont).fn_table;
             (*pcont).bdev.ctxt = pcont as *mut c_void;
-            Bdev::from_inner_ptr(&mut (*pcont).bdev)
+            Ok(Bdev::from_inner_ptr(&mut (*pcont).bdev))
         }
     }
 }
@@ -313,27 +799,48 @@ where
     spdk_get_io_channel(io_dev.get_io_device_id())
 }
 
-/// TODO
+/// Called by SPDK to get the spin time of an I/O channel of the Bdev.
+/// This function forwards the call to `BdevOps::spin_time`.
 ///
 /// # Generic Arguments
 ///
-/// * `BdevData`: TODO
+/// * `BdevData`: Bdev data type.
 ///
 /// # Arguments
 ///
-/// * `ctx`: Pointer to a Bdev context, which is a pointer to `Container<_>` in
-///   our case.
+/// * `ch`: I/O channel.
+unsafe extern "C" fn inner_get_spin_time<BdevData>(
+    ch: *mut spdk_io_channel,
+) -> u64
+where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let mut chan = IoChannel::<BdevData::ChannelData>::from_ptr(ch);
+    BdevData::spin_time(chan.channel_data_mut())
+}
+
+/// Called by SPDK to get a module-specific context of the Bdev.
+/// This function forwards the call to `BdevOps::get_module_ctx`, and returns
+/// a null pointer if the Bdev has no module context.
 ///
-/// # Safety
+/// # Generic Arguments
 ///
-/// TODO
+/// * `BdevData`: Bdev data type.
+///
+/// # Arguments
+///
+/// * `ctx`: Pointer to a Bdev context, which is a pointer to `Container<_>` in
+///   our case.
 unsafe extern "C" fn inner_bdev_get_module_ctx<BdevData>(
-    _ctx: *mut c_void,
+    ctx: *mut c_void,
 ) -> *mut c_void
 where
-    BdevData: BdevOps<BdevData = BdevData>,
+    BdevData: WithBdevModuleCtx + BdevOps<BdevData = BdevData>,
 {
-    todo!()
+    let c = Container::<BdevData>::from_ptr(ctx);
+    c.data.get_module_ctx().map_or(std::ptr::null_mut(), |p| {
+        p as *const BdevData::ModuleCtx as *mut c_void
+    })
 }
 
 /// Called by SPDK to determine if a particular I/O channel for the given Bdev.
@@ -392,3 +899,488 @@ where
     // TODO: error processing?
     0
 }
+
+/// Called by SPDK to write the configuration of the given Bdev.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `bdev`: Pointer to the SPDK Bdev.
+/// * `w`: JSON write context.
+///
+/// # Safety
+///
+/// TODO
+unsafe extern "C" fn inner_write_config_json<BdevData>(
+    bdev: *mut spdk_bdev,
+    w: *mut spdk_json_write_ctx,
+) where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let c = Container::<BdevData>::from_ptr((*bdev).ctxt);
+    c.data.write_config_json(JsonWriteContext::from_ptr(w));
+}
+
+/// Called by SPDK to write driver-specific statistics of the given Bdev.
+/// Passes the last per-channel snapshot to the Bdev, and then starts
+/// collecting a new one if the Bdev reports per-channel statistics.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `ctx`: Pointer to a Bdev context, which is a pointer to `Container<_>` in
+///   our case.
+/// * `w`: JSON write context.
+///
+/// # Safety
+///
+/// TODO
+unsafe extern "C" fn inner_dump_device_stat_json<BdevData>(
+    ctx: *mut c_void,
+    w: *mut spdk_json_write_ctx,
+) where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let c = Container::<BdevData>::from_ptr(ctx);
+
+    let snapshot = c.channel_stats.lock().latest.clone();
+    c.data.dump_device_stat_json(
+        JsonWriteContext::from_ptr(w),
+        snapshot.as_deref(),
+    );
+
+    if !c.data.has_channel_stat() {
+        return;
+    }
+
+    // Collect a new snapshot for the next call, unless already in progress.
+    {
+        let mut stats = c.channel_stats.lock();
+        if stats.pending.is_some() {
+            return;
+        }
+        stats.pending = Some(Vec::new());
+    }
+
+    if !for_each_bdev_channel::<BdevData>(
+        ctx,
+        inner_collect_channel_stat::<BdevData>,
+        inner_collect_channel_stat_done::<BdevData>,
+    ) {
+        c.channel_stats.lock().pending = None;
+    }
+}
+
+/// Collects driver-specific statistics of a single I/O channel.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `i`: I/O channel iterator; its context is a `ChannelIterCtx`.
+unsafe extern "C" fn inner_collect_channel_stat<BdevData>(
+    i: *mut spdk_io_channel_iter,
+) where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let c = ChannelIterCtx::container::<BdevData>(i);
+    let chan = IoChannel::<BdevData::ChannelData>::from_iter(i);
+
+    if let Some(stat) = c.data.channel_stat(chan.channel_data()) {
+        if let Some(pending) = c.channel_stats.lock().pending.as_mut() {
+            pending.push(stat);
+        }
+    }
+
+    spdk_for_each_channel_continue(i, 0);
+}
+
+/// Completion of per-channel statistics collection: the collected
+/// statistics become the snapshot given to the next dump.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `i`: I/O channel iterator; its context is a `ChannelIterCtx`.
+/// * `_status`: Iteration status.
+unsafe extern "C" fn inner_collect_channel_stat_done<BdevData>(
+    i: *mut spdk_io_channel_iter,
+    _status: i32,
+) where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    {
+        let c = ChannelIterCtx::container::<BdevData>(i);
+        let mut stats = c.channel_stats.lock();
+
+        if let Some(pending) = stats.pending.take() {
+            stats.latest = Some(pending);
+        }
+    }
+
+    ChannelIterCtx::finish(i);
+}
+
+/// Called by SPDK to reset driver-specific statistics of the given Bdev.
+/// Resets the Bdev-wide statistics, and then statistics of each I/O
+/// channel on its own thread.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `ctx`: Pointer to a Bdev context, which is a pointer to `Container<_>` in
+///   our case.
+///
+/// # Safety
+///
+/// TODO
+unsafe extern "C" fn inner_reset_device_stat<BdevData>(ctx: *mut c_void)
+where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let c = Container::<BdevData>::from_ptr(ctx);
+    c.data.reset_device_stat();
+
+    for_each_bdev_channel::<BdevData>(
+        ctx,
+        inner_reset_channel_stat::<BdevData>,
+        inner_reset_channel_stat_done,
+    );
+}
+
+/// Resets driver-specific statistics of a single I/O channel.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `i`: I/O channel iterator; its context is a `ChannelIterCtx`.
+unsafe extern "C" fn inner_reset_channel_stat<BdevData>(
+    i: *mut spdk_io_channel_iter,
+) where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let c = ChannelIterCtx::container::<BdevData>(i);
+    let mut chan = IoChannel::<BdevData::ChannelData>::from_iter(i);
+    c.data.reset_channel_stat(chan.channel_data_mut());
+    spdk_for_each_channel_continue(i, 0);
+}
+
+/// Completion of per-channel statistics reset.
+///
+/// # Arguments
+///
+/// * `i`: I/O channel iterator; its context is a `ChannelIterCtx`.
+/// * `_status`: Iteration status.
+unsafe extern "C" fn inner_reset_channel_stat_done(
+    i: *mut spdk_io_channel_iter,
+    _status: i32,
+) {
+    ChannelIterCtx::finish(i);
+}
+
+/// Context of an iteration over the I/O channels of a Bdev.
+/// The descriptor keeps the Bdev, and thus its container, from being
+/// destroyed until the iteration completes.
+struct ChannelIterCtx {
+    /// Pointer to the Bdev context, which is a pointer to `Container<_>`.
+    ctx: *mut c_void,
+    /// Descriptor open for the time of the iteration.
+    desc: BdevDesc<()>,
+}
+
+impl ChannelIterCtx {
+    /// Returns the container of the Bdev being iterated.
+    ///
+    /// # Arguments
+    ///
+    /// * `i`: I/O channel iterator.
+    unsafe fn container<'a, BdevData>(
+        i: *mut spdk_io_channel_iter,
+    ) -> Pin<&'a Container<BdevData>>
+    where
+        BdevData: BdevOps<BdevData = BdevData>,
+    {
+        let it = spdk_io_channel_iter_get_ctx(i) as *mut ChannelIterCtx;
+        Container::<BdevData>::from_ptr((*it).ctx)
+    }
+
+    /// Frees the context once the iteration has completed, and closes its
+    /// descriptor.
+    ///
+    /// # Arguments
+    ///
+    /// * `i`: I/O channel iterator.
+    unsafe fn finish(i: *mut spdk_io_channel_iter) {
+        let mut it = Box::from_raw(
+            spdk_io_channel_iter_get_ctx(i) as *mut ChannelIterCtx
+        );
+        it.desc.close();
+    }
+}
+
+/// Calls a function for each I/O channel of a Bdev, on the channel's
+/// thread, and then the completion on the current thread.
+/// A descriptor of the Bdev is kept open until the iteration completes.
+/// Returns false if the descriptor cannot be open, in which case nothing
+/// is called.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `ctx`: Pointer to a Bdev context, which is a pointer to `Container<_>` in
+///   our case.
+/// * `f`: Function to call for each channel.
+/// * `done`: Completion.
+unsafe fn for_each_bdev_channel<BdevData>(
+    ctx: *mut c_void,
+    f: unsafe extern "C" fn(*mut spdk_io_channel_iter),
+    done: unsafe extern "C" fn(*mut spdk_io_channel_iter, i32),
+) -> bool
+where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let c = Container::<BdevData>::from_ptr(ctx);
+    let bdev = Bdev::<BdevData>::from_inner_ptr(&c.bdev as *const _ as *mut _);
+
+    let desc = match BdevDesc::<()>::open(bdev.name(), false, |_, _| {}) {
+        Ok(desc) => desc,
+        Err(e) => {
+            error!(
+                "{}: failed to open Bdev to iterate its channels: {}",
+                bdev.name(),
+                e
+            );
+            return false;
+        }
+    };
+
+    let it = Box::into_raw(Box::new(ChannelIterCtx {
+        ctx,
+        desc,
+    }));
+
+    spdk_for_each_channel(
+        c.data.get_io_device().get_io_device_id(),
+        Some(f),
+        it as *mut c_void,
+        Some(done),
+    );
+
+    true
+}
+
+/// Called by SPDK to get the memory domains supported by the given Bdev.
+/// Fills at most `array_size` entries, and returns the total number of
+/// memory domains.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `ctx`: Pointer to a Bdev context, which is a pointer to `Container<_>` in
+///   our case.
+/// * `domains`: Array to fill; may be null.
+/// * `array_size`: Size of the array.
+///
+/// # Safety
+///
+/// TODO
+unsafe extern "C" fn inner_get_memory_domains<BdevData>(
+    ctx: *mut c_void,
+    domains: *mut *mut spdk_memory_domain,
+    array_size: i32,
+) -> i32
+where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let c = Container::<BdevData>::from_ptr(ctx);
+    let res = c.data.get_memory_domains();
+
+    if !domains.is_null() {
+        for (i, d) in res.iter().take(array_size.max(0) as usize).enumerate() {
+            *domains.add(i) = d.legacy_as_ptr();
+        }
+    }
+
+    res.len() as i32
+}
+
+/// Called by SPDK to determine if the given Bdev supports accel sequences
+/// for an I/O type.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: TODO
+///
+/// # Arguments
+///
+/// * `ctx`: Pointer to a Bdev context, which is a pointer to `Container<_>` in
+///   our case.
+/// * `io_type`: I/O type.
+///
+/// # Safety
+///
+/// TODO
+unsafe extern "C" fn inner_accel_sequence_supported<BdevData>(
+    ctx: *mut c_void,
+    io_type: spdk_bdev_io_type,
+) -> bool
+where
+    BdevData: BdevOps<BdevData = BdevData>,
+{
+    let c = Container::<BdevData>::from_ptr(ctx);
+    c.data.accel_sequence_supported(IoType::from(io_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::NonNull;
+
+    /// Makes a module for builders that are validated but never built.
+    fn test_module() -> BdevModule {
+        BdevModule::from_ptr(NonNull::dangling().as_ptr())
+    }
+
+    /// Makes a builder with all mandatory parameters set.
+    fn builder(m: &BdevModule) -> BdevBuilder<'_, ()> {
+        let mut b = BdevBuilder::new(m)
+            .with_name("test0")
+            .with_product_name("Test Disk")
+            .with_block_length(512)
+            .with_block_count(1024)
+            .with_required_alignment(0);
+
+        // Validation only checks that the data is set: an empty function
+        // table does not drag SPDK callbacks into the test.
+        b.fn_table = Some(unsafe { zeroed() });
+        b.data = Some(());
+        b
+    }
+
+    /// Checks that a builder fails validation for conflicting parameters.
+    fn assert_invalid(b: BdevBuilder<'_, ()>) {
+        assert!(matches!(
+            b.validate(),
+            Err(SpdkError::BdevParamsInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn valid() {
+        let m = test_module();
+        assert!(builder(&m).validate().is_ok());
+        assert!(builder(&m)
+            .with_physical_block_length(4096)
+            .with_write_unit_size(8)
+            .with_split_on_write_unit(true)
+            .with_optimal_io_boundary(8)
+            .with_split_on_optimal_io_boundary(true)
+            .with_interleaved_metadata(8)
+            .with_dif(DifType::Type1, true, dif_check_flags::REFTAG)
+            .validate()
+            .is_ok());
+        assert!(builder(&m)
+            .with_zoned(128, 4, 8)
+            .with_max_zone_append_size(64)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn missing_params() {
+        let m = test_module();
+        let missing = |b: BdevBuilder<'_, ()>, p: &str| match b.validate() {
+            Err(SpdkError::BdevParamMissing {
+                param,
+            }) => assert_eq!(param, p),
+            r => panic!("unexpected result for '{}': {:?}", p, r),
+        };
+
+        missing(BdevBuilder::new(&m), "name");
+        missing(BdevBuilder::new(&m).with_name("test0"), "product name");
+
+        let mut b = builder(&m);
+        b.blockcnt = None;
+        missing(b, "block count");
+
+        let mut b = builder(&m);
+        b.fn_table = None;
+        missing(b, "data");
+    }
+
+    #[test]
+    fn invalid_geometry() {
+        let m = test_module();
+        assert_invalid(builder(&m).with_block_length(0));
+        assert_invalid(builder(&m).with_block_count(0));
+        assert_invalid(builder(&m).with_physical_block_length(1000));
+    }
+
+    #[test]
+    fn invalid_split() {
+        let m = test_module();
+        assert_invalid(builder(&m).with_split_on_write_unit(true));
+        assert_invalid(builder(&m).with_split_on_optimal_io_boundary(true));
+        assert_invalid(
+            builder(&m)
+                .with_write_unit_size(8)
+                .with_split_on_write_unit(true)
+                .with_optimal_io_boundary(16)
+                .with_split_on_optimal_io_boundary(true),
+        );
+    }
+
+    #[test]
+    fn invalid_metadata() {
+        let m = test_module();
+        assert_invalid(builder(&m).with_interleaved_metadata(512));
+        assert_invalid(builder(&m).with_separate_metadata(4).with_dif(
+            DifType::Type1,
+            false,
+            0,
+        ));
+        assert_invalid(builder(&m).with_dif(
+            DifType::Disable,
+            false,
+            dif_check_flags::GUARD,
+        ));
+        assert_invalid(builder(&m).with_separate_metadata(8).with_dif(
+            DifType::Type3,
+            false,
+            dif_check_flags::REFTAG,
+        ));
+    }
+
+    #[test]
+    fn invalid_zones() {
+        let m = test_module();
+        assert_invalid(builder(&m).with_zoned(0, 0, 0));
+        assert_invalid(builder(&m).with_zoned(100, 0, 0));
+        assert_invalid(builder(&m).with_zoned(128, 8, 4));
+        assert_invalid(builder(&m).with_max_zone_append_size(64));
+        assert_invalid(builder(&m).with_optimal_open_zones(2));
+    }
+}
@@ -15,6 +15,7 @@ use crate::{
         spdk_bdev_desc_get_bdev,
         spdk_bdev_event_type,
         spdk_bdev_get_io_channel,
+        spdk_bdev_get_module_ctx,
         spdk_bdev_open_ext,
         SPDK_BDEV_EVENT_MEDIA_MANAGEMENT,
         SPDK_BDEV_EVENT_REMOVE,
@@ -22,6 +23,11 @@ use crate::{
     },
     Bdev,
     BdevOps,
+    DmaError,
+    IoStatus,
+    IoType,
+    NvmeStatus,
+    WithBdevModuleCtx,
 };
 
 /// Bdev descriptor errors.
@@ -39,6 +45,66 @@ pub enum BdevDescError {
         source: nix::errno::Errno,
         bdev_name: String,
     },
+    #[snafu(display(
+        "Failed to submit {:?} I/O to '{}': {}",
+        io_type,
+        bdev_name,
+        source
+    ))]
+    IoSubmit {
+        source: nix::errno::Errno,
+        io_type: IoType,
+        bdev_name: String,
+    },
+    #[snafu(display(
+        "{:?} I/O to '{}' failed: {:?} ({:?})",
+        io_type,
+        bdev_name,
+        io_status,
+        nvme_status
+    ))]
+    IoFailed {
+        io_type: IoType,
+        bdev_name: String,
+        io_status: IoStatus,
+        nvme_status: NvmeStatus,
+    },
+    #[snafu(display(
+        "{:?} I/O to '{}' failed: data miscompare",
+        io_type,
+        bdev_name
+    ))]
+    IoMiscompare { io_type: IoType, bdev_name: String },
+    #[snafu(display(
+        "Failed to allocate I/O buffer for '{}': {}",
+        bdev_name,
+        source
+    ))]
+    IoBufAlloc { source: DmaError, bdev_name: String },
+    #[snafu(display(
+        "I/O range {}+{} is not aligned to the block size of '{}' ({})",
+        offset,
+        len,
+        bdev_name,
+        block_len
+    ))]
+    IoAlignment {
+        offset: u64,
+        len: u64,
+        bdev_name: String,
+        block_len: u32,
+    },
+    #[snafu(display(
+        "Failed to queue I/O resource wait for '{}': {}",
+        bdev_name,
+        source
+    ))]
+    IoWait {
+        source: nix::errno::Errno,
+        bdev_name: String,
+    },
+    #[snafu(display("Invalid parameters for '{}': {}", bdev_name, msg))]
+    InvalidParams { msg: String, bdev_name: String },
 }
 
 /// Wrapper for `spdk_bdev_desc`.
@@ -147,6 +213,23 @@ where
         }
     }
 
+    /// Returns the module-specific context of the underlying Bdev, if
+    /// the Bdev has been created with the data type `T` recorded
+    /// (see `BdevBuilder::with_data_type()`) and provides
+    /// a context (see `WithBdevModuleCtx`). Returns `None` for Bdevs
+    /// of other types, including Bdevs not created by `BdevBuilder`.
+    pub fn module_ctx<T>(&self) -> Option<&T::ModuleCtx>
+    where
+        T: WithBdevModuleCtx + 'static,
+    {
+        if !self.bdev().has_data_type::<T>() {
+            return None;
+        }
+
+        let ctx = unsafe { spdk_bdev_get_module_ctx(self.as_ptr()) };
+        unsafe { (ctx as *const T::ModuleCtx).as_ref() }
+    }
+
     /// Gains exclusive access over a block range, and returns
     /// a lock object that must be used to unlock the range.
     pub async fn lock_lba_range(
@@ -0,0 +1,692 @@
+///! Asynchronous I/O methods of `BdevDesc<>` wrapper.
+use std::os::raw::c_void;
+
+use futures::channel::oneshot;
+use nix::errno::Errno;
+
+use crate::{
+    libspdk::{
+        spdk_bdev_comparev_and_writev_blocks,
+        spdk_bdev_comparev_blocks,
+        spdk_bdev_copy_blocks,
+        spdk_bdev_flush_blocks,
+        spdk_bdev_free_io,
+        spdk_bdev_io,
+        spdk_bdev_io_completion_cb,
+        spdk_bdev_io_get_append_location,
+        spdk_bdev_io_get_nvme_status,
+        spdk_bdev_readv_blocks,
+        spdk_bdev_reset,
+        spdk_bdev_unmap_blocks,
+        spdk_bdev_write_zeroes_blocks,
+        spdk_bdev_writev_blocks,
+    },
+    AsIoVecPtr,
+    BdevDesc,
+    BdevDescError,
+    BdevOps,
+    DmaBuf,
+    IoChannel,
+    IoStatus,
+    IoType,
+    IoVec,
+    NvmeStatus,
+};
+
+/// Completion of an I/O submitted via a bdev descriptor.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct IoCompletion {
+    /// True if the I/O has succeeded.
+    pub(crate) success: bool,
+    /// Bdev I/O status.
+    pub(crate) io_status: IoStatus,
+    /// NVMe status of the I/O.
+    pub(crate) nvme_status: NvmeStatus,
+    /// Dword 0 of the NVMe completion.
+    pub(crate) cdw0: u32,
+    /// Starting block of the data written by a zone append.
+    pub(crate) append_location: u64,
+}
+
+impl IoCompletion {
+    /// Makes a new `IoCompletion` from a completed SPDK bdev I/O.
+    ///
+    /// # Arguments
+    ///
+    /// * `bio`: Completed bdev I/O.
+    /// * `success`: Success flag passed to the completion callback.
+    pub(crate) unsafe fn from_bdev_io(
+        bio: *mut spdk_bdev_io,
+        success: bool,
+    ) -> Self {
+        let mut cdw0: u32 = 0;
+        let mut sct: i32 = 0;
+        let mut sc: i32 = 0;
+
+        spdk_bdev_io_get_nvme_status(bio, &mut cdw0, &mut sct, &mut sc);
+
+        let append_location =
+            if (*bio).type_ as u32 == u32::from(IoType::ZoneAppend) {
+                spdk_bdev_io_get_append_location(bio)
+            } else {
+                0
+            };
+
+        Self {
+            success,
+            io_status: (*bio).internal.status.into(),
+            nvme_status: NvmeStatus::from((sct, sc)),
+            cdw0,
+            append_location,
+        }
+    }
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Reads data at the given byte offset into a buffer.
+    /// The offset and the buffer length must be multiples of the block size.
+    /// The buffer is owned by the I/O until it completes, and is returned
+    /// once the data has been read.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `offset`: Offset in bytes.
+    /// * `buf`: Buffer to read into.
+    pub async fn read_at(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        offset: u64,
+        buf: DmaBuf,
+    ) -> Result<DmaBuf, BdevDescError> {
+        let (offset_blocks, num_blocks) =
+            self.bytes_to_blocks(offset, buf.len())?;
+
+        let iovs = [buf.to_io_vec()];
+        let (_, (buf, _)) = self
+            .submit_io_with(
+                IoType::Read,
+                (buf, iovs),
+                |(_, iovs), cb, arg| unsafe {
+                    spdk_bdev_readv_blocks(
+                        self.as_ptr(),
+                        chan.legacy_as_ptr(),
+                        iovs.as_io_vec_mut_ptr(),
+                        iovs.len() as i32,
+                        offset_blocks,
+                        num_blocks,
+                        cb,
+                        arg,
+                    )
+                },
+            )
+            .await?;
+
+        Ok(buf)
+    }
+
+    /// Writes data from a buffer at the given byte offset.
+    /// The offset and the buffer length must be multiples of the block size.
+    /// The buffer is owned by the I/O until it completes, and is returned
+    /// once the data has been written.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `offset`: Offset in bytes.
+    /// * `buf`: Buffer to write from.
+    pub async fn write_at(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        offset: u64,
+        buf: DmaBuf,
+    ) -> Result<DmaBuf, BdevDescError> {
+        let (offset_blocks, num_blocks) =
+            self.bytes_to_blocks(offset, buf.len())?;
+
+        let iovs = [buf.to_io_vec()];
+        let (_, (buf, _)) = self
+            .submit_io_with(
+                IoType::Write,
+                (buf, iovs),
+                |(_, iovs), cb, arg| unsafe {
+                    spdk_bdev_writev_blocks(
+                        self.as_ptr(),
+                        chan.legacy_as_ptr(),
+                        iovs.as_io_vec_mut_ptr(),
+                        iovs.len() as i32,
+                        offset_blocks,
+                        num_blocks,
+                        cb,
+                        arg,
+                    )
+                },
+            )
+            .await?;
+
+        Ok(buf)
+    }
+
+    /// Reads blocks into a vector of buffers.
+    ///
+    /// # Safety
+    ///
+    /// The memory the I/O vector points to must stay valid until the I/O
+    /// completes: the returned future must be polled to completion, and
+    /// must not be dropped before. Use `read_at()` for an owned buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `iovs`: Buffers to read into.
+    /// * `offset_blocks`: Offset in blocks.
+    /// * `num_blocks`: Number of blocks to read.
+    pub async unsafe fn readv_blocks(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        iovs: &[IoVec],
+        offset_blocks: u64,
+        num_blocks: u64,
+    ) -> Result<(), BdevDescError> {
+        // The I/O vector itself is copied, so that it lives as long as
+        // the I/O.
+        self.submit_io_with(IoType::Read, iovs.to_vec(), |iovs, cb, arg| {
+            spdk_bdev_readv_blocks(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                iovs.as_io_vec_mut_ptr(),
+                iovs.len() as i32,
+                offset_blocks,
+                num_blocks,
+                cb,
+                arg,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Writes blocks from a vector of buffers.
+    ///
+    /// # Safety
+    ///
+    /// The memory the I/O vector points to must stay valid until the I/O
+    /// completes: the returned future must be polled to completion, and
+    /// must not be dropped before. Use `write_at()` for an owned buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `iovs`: Buffers to write from.
+    /// * `offset_blocks`: Offset in blocks.
+    /// * `num_blocks`: Number of blocks to write.
+    pub async unsafe fn writev_blocks(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        iovs: &[IoVec],
+        offset_blocks: u64,
+        num_blocks: u64,
+    ) -> Result<(), BdevDescError> {
+        // The I/O vector itself is copied, so that it lives as long as
+        // the I/O.
+        self.submit_io_with(IoType::Write, iovs.to_vec(), |iovs, cb, arg| {
+            spdk_bdev_writev_blocks(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                iovs.as_io_vec_mut_ptr(),
+                iovs.len() as i32,
+                offset_blocks,
+                num_blocks,
+                cb,
+                arg,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Unmaps (deallocates) a range of blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `offset_blocks`: Offset in blocks.
+    /// * `num_blocks`: Number of blocks to unmap.
+    pub async fn unmap_blocks(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        offset_blocks: u64,
+        num_blocks: u64,
+    ) -> Result<(), BdevDescError> {
+        self.submit_io(IoType::Unmap, |cb, arg| unsafe {
+            spdk_bdev_unmap_blocks(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                offset_blocks,
+                num_blocks,
+                cb,
+                arg,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Writes zeroes to a range of blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `offset_blocks`: Offset in blocks.
+    /// * `num_blocks`: Number of blocks to zero.
+    pub async fn write_zeroes_blocks(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        offset_blocks: u64,
+        num_blocks: u64,
+    ) -> Result<(), BdevDescError> {
+        self.submit_io(IoType::WriteZeros, |cb, arg| unsafe {
+            spdk_bdev_write_zeroes_blocks(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                offset_blocks,
+                num_blocks,
+                cb,
+                arg,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Flushes a range of blocks to persistent storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `offset_blocks`: Offset in blocks.
+    /// * `num_blocks`: Number of blocks to flush.
+    pub async fn flush_blocks(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        offset_blocks: u64,
+        num_blocks: u64,
+    ) -> Result<(), BdevDescError> {
+        self.submit_io(IoType::Flush, |cb, arg| unsafe {
+            spdk_bdev_flush_blocks(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                offset_blocks,
+                num_blocks,
+                cb,
+                arg,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Resets the block device. All outstanding I/Os are aborted.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    pub async fn reset(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+    ) -> Result<(), BdevDescError> {
+        self.submit_io(IoType::Reset, |cb, arg| unsafe {
+            spdk_bdev_reset(self.as_ptr(), chan.legacy_as_ptr(), cb, arg)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Compares the content of a range of blocks with a vector of buffers.
+    /// Fails with `BdevDescError::IoMiscompare` if the data differs.
+    ///
+    /// # Safety
+    ///
+    /// The memory the I/O vector points to must stay valid until the I/O
+    /// completes: the returned future must be polled to completion, and
+    /// must not be dropped before.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `iovs`: Buffers to compare with.
+    /// * `offset_blocks`: Offset in blocks.
+    /// * `num_blocks`: Number of blocks to compare.
+    pub async unsafe fn compare_blocks(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        iovs: &[IoVec],
+        offset_blocks: u64,
+        num_blocks: u64,
+    ) -> Result<(), BdevDescError> {
+        // The I/O vector itself is copied, so that it lives as long as
+        // the I/O.
+        self.submit_io_with(IoType::Compare, iovs.to_vec(), |iovs, cb, arg| {
+            spdk_bdev_comparev_blocks(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                iovs.as_io_vec_mut_ptr(),
+                iovs.len() as i32,
+                offset_blocks,
+                num_blocks,
+                cb,
+                arg,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Atomically compares a range of blocks with a vector of buffers, and
+    /// writes another vector of buffers to it if the data matches.
+    /// Fails with `BdevDescError::IoMiscompare` if the data differs, in which
+    /// case nothing is written.
+    ///
+    /// # Safety
+    ///
+    /// The memory both I/O vectors point to must stay valid until the I/O
+    /// completes: the returned future must be polled to completion, and
+    /// must not be dropped before.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `compare_iovs`: Buffers to compare with.
+    /// * `write_iovs`: Buffers to write from.
+    /// * `offset_blocks`: Offset in blocks.
+    /// * `num_blocks`: Number of blocks to compare and write.
+    pub async unsafe fn comparev_and_writev_blocks(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        compare_iovs: &[IoVec],
+        write_iovs: &[IoVec],
+        offset_blocks: u64,
+        num_blocks: u64,
+    ) -> Result<(), BdevDescError> {
+        // The I/O vectors themselves are copied, so that they live as long
+        // as the I/O.
+        self.submit_io_with(
+            IoType::CompareAndWrite,
+            (compare_iovs.to_vec(), write_iovs.to_vec()),
+            |(compare_iovs, write_iovs), cb, arg| {
+                spdk_bdev_comparev_and_writev_blocks(
+                    self.as_ptr(),
+                    chan.legacy_as_ptr(),
+                    compare_iovs.as_io_vec_mut_ptr(),
+                    compare_iovs.len() as i32,
+                    write_iovs.as_io_vec_mut_ptr(),
+                    write_iovs.len() as i32,
+                    offset_blocks,
+                    num_blocks,
+                    cb,
+                    arg,
+                )
+            },
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Copies a range of blocks to another location on the same device.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `dst_offset_blocks`: Destination offset in blocks.
+    /// * `src_offset_blocks`: Source offset in blocks.
+    /// * `num_blocks`: Number of blocks to copy.
+    pub async fn copy_blocks(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        dst_offset_blocks: u64,
+        src_offset_blocks: u64,
+        num_blocks: u64,
+    ) -> Result<(), BdevDescError> {
+        self.submit_io(IoType::Copy, |cb, arg| unsafe {
+            spdk_bdev_copy_blocks(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                dst_offset_blocks,
+                src_offset_blocks,
+                num_blocks,
+                cb,
+                arg,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Submits an I/O and waits for its completion.
+    /// Returns an error if the I/O fails to submit or completes with
+    /// a failure. A miscompare is reported as `BdevDescError::IoMiscompare`.
+    ///
+    /// # Arguments
+    ///
+    /// * `io_type`: Type of the I/O, used for error reporting.
+    /// * `f`: Closure that calls an SPDK I/O submission function with the given
+    ///   completion callback and its argument, and returns its result.
+    pub(crate) async fn submit_io<F>(
+        &self,
+        io_type: IoType,
+        f: F,
+    ) -> Result<IoCompletion, BdevDescError>
+    where
+        F: FnOnce(spdk_bdev_io_completion_cb, *mut c_void) -> i32,
+    {
+        self.submit_io_with(io_type, (), |_, cb, arg| f(cb, arg))
+            .await
+            .map(|(cpl, _)| cpl)
+    }
+
+    /// Same as `submit_io()`, but moves the given data into the I/O context
+    /// for the lifetime of the I/O, and returns it along with the
+    /// completion. Buffers and I/O vectors passed to SPDK must be owned
+    /// this way, so that they outlive the I/O even if the waiting future
+    /// is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `io_type`: Type of the I/O, used for error reporting.
+    /// * `data`: Data owned by the I/O.
+    /// * `f`: Closure that calls an SPDK I/O submission function with the owned
+    ///   data, the given completion callback and its argument, and returns its
+    ///   result.
+    pub(crate) async fn submit_io_with<T, F>(
+        &self,
+        io_type: IoType,
+        data: T,
+        f: F,
+    ) -> Result<(IoCompletion, T), BdevDescError>
+    where
+        T: 'static,
+        F: FnOnce(&mut T, spdk_bdev_io_completion_cb, *mut c_void) -> i32,
+    {
+        let (cpl, data) = self.submit_io_raw_with(io_type, data, f).await?;
+        self.check_completion(io_type, cpl).map(|cpl| (cpl, data))
+    }
+
+    /// Submits an I/O and waits for its completion.
+    /// Unlike `submit_io()`, a completed I/O is returned as is, whether it
+    /// has succeeded or not.
+    ///
+    /// # Arguments
+    ///
+    /// * `io_type`: Type of the I/O, used for error reporting.
+    /// * `f`: Closure that calls an SPDK I/O submission function with the given
+    ///   completion callback and its argument, and returns its result.
+    pub(crate) async fn submit_io_raw<F>(
+        &self,
+        io_type: IoType,
+        f: F,
+    ) -> Result<IoCompletion, BdevDescError>
+    where
+        F: FnOnce(spdk_bdev_io_completion_cb, *mut c_void) -> i32,
+    {
+        self.submit_io_raw_with(io_type, (), |_, cb, arg| f(cb, arg))
+            .await
+            .map(|(cpl, _)| cpl)
+    }
+
+    /// Same as `submit_io_raw()`, but moves the given data into the I/O
+    /// context for the lifetime of the I/O (see `submit_io_with()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `io_type`: Type of the I/O, used for error reporting.
+    /// * `data`: Data owned by the I/O.
+    /// * `f`: Closure that calls an SPDK I/O submission function with the owned
+    ///   data, the given completion callback and its argument, and returns its
+    ///   result.
+    pub(crate) async fn submit_io_raw_with<T, F>(
+        &self,
+        io_type: IoType,
+        data: T,
+        f: F,
+    ) -> Result<(IoCompletion, T), BdevDescError>
+    where
+        T: 'static,
+        F: FnOnce(&mut T, spdk_bdev_io_completion_cb, *mut c_void) -> i32,
+    {
+        let (s, r) = oneshot::channel::<(IoCompletion, T)>();
+        let ctx = Box::into_raw(Box::new(IoContext {
+            sender: s,
+            data,
+        }));
+
+        let rc = unsafe {
+            f(
+                &mut (*ctx).data,
+                Some(inner_io_completion_cb::<T>),
+                ctx as *mut c_void,
+            )
+        };
+        if rc != 0 {
+            unsafe { drop(Box::from_raw(ctx)) };
+            return Err(BdevDescError::IoSubmit {
+                source: Errno::from_i32(rc.abs()),
+                io_type,
+                bdev_name: self.bdev().name().to_owned(),
+            });
+        }
+
+        Ok(r.await.expect("I/O completion sender is gone"))
+    }
+
+    /// Converts a failed I/O completion into an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `io_type`: Type of the I/O, used for error reporting.
+    /// * `cpl`: I/O completion.
+    pub(crate) fn check_completion(
+        &self,
+        io_type: IoType,
+        cpl: IoCompletion,
+    ) -> Result<IoCompletion, BdevDescError> {
+        if cpl.success {
+            Ok(cpl)
+        } else if cpl.io_status == IoStatus::MisCompared
+            || cpl.nvme_status == NvmeStatus::COMPARE_FAILURE
+        {
+            // Miscompares reported by NVMe bdevs come as NVMe errors.
+            Err(BdevDescError::IoMiscompare {
+                io_type,
+                bdev_name: self.bdev().name().to_owned(),
+            })
+        } else {
+            Err(BdevDescError::IoFailed {
+                io_type,
+                bdev_name: self.bdev().name().to_owned(),
+                io_status: cpl.io_status,
+                nvme_status: cpl.nvme_status,
+            })
+        }
+    }
+
+    /// Allocates a zeroed DMA buffer suitable for I/O to this descriptor's
+    /// bdev.
+    ///
+    /// # Arguments
+    ///
+    /// * `size`: Buffer size in bytes.
+    pub(crate) fn alloc_io_buf(
+        &self,
+        size: u64,
+    ) -> Result<DmaBuf, BdevDescError> {
+        let bdev = self.bdev();
+        DmaBuf::new(size, bdev.alignment()).map_err(|source| {
+            BdevDescError::IoBufAlloc {
+                source,
+                bdev_name: bdev.name().to_owned(),
+            }
+        })
+    }
+
+    /// Converts a byte range into a block range, checking the alignment.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset`: Offset in bytes.
+    /// * `len`: Length in bytes.
+    fn bytes_to_blocks(
+        &self,
+        offset: u64,
+        len: u64,
+    ) -> Result<(u64, u64), BdevDescError> {
+        let bdev = self.bdev();
+        let block_len = bdev.block_len();
+        let bl = block_len as u64;
+
+        if len == 0 || offset % bl != 0 || len % bl != 0 {
+            return Err(BdevDescError::IoAlignment {
+                offset,
+                len,
+                bdev_name: bdev.name().to_owned(),
+                block_len,
+            });
+        }
+
+        Ok((offset / bl, len / bl))
+    }
+}
+
+/// Context of an I/O submitted via `BdevDesc::submit_io_raw_with()`.
+struct IoContext<T> {
+    /// Sender of the completion and the owned data.
+    sender: oneshot::Sender<(IoCompletion, T)>,
+    /// Data owned by the I/O until it completes.
+    data: T,
+}
+
+/// Completion callback for I/Os submitted via `BdevDesc::submit_io()`.
+/// Captures the completion status, frees the bdev I/O, and gives the data
+/// owned by the I/O back.
+///
+/// # Arguments
+///
+/// * `bio`: Completed bdev I/O.
+/// * `success`: True if the I/O has succeeded.
+/// * `arg`: I/O context.
+unsafe extern "C" fn inner_io_completion_cb<T>(
+    bio: *mut spdk_bdev_io,
+    success: bool,
+    arg: *mut c_void,
+) {
+    let ctx = Box::from_raw(arg as *mut IoContext<T>);
+    let cpl = IoCompletion::from_bdev_io(bio, success);
+
+    spdk_bdev_free_io(bio);
+
+    // The receiver may be gone if the waiting future has been dropped:
+    // the owned data is dropped here then, after the I/O has completed.
+    ctx.sender.send((cpl, ctx.data)).ok();
+}
@@ -1,5 +1,6 @@
 ///! TODO
 use core::fmt;
+use nix::errno::Errno;
 use std::{
     fmt::{Debug, Formatter},
     marker::PhantomData,
@@ -12,7 +13,13 @@ use crate::{
     libspdk::{
         spdk_bdev_io,
         spdk_bdev_io_complete,
+        spdk_bdev_io_complete_aio_status,
+        spdk_bdev_io_complete_nvme_status,
+        spdk_bdev_io_complete_scsi_status,
+        spdk_bdev_io_get_aio_status,
         spdk_bdev_io_get_buf,
+        spdk_bdev_io_get_nvme_status,
+        spdk_bdev_io_get_scsi_status,
         spdk_io_channel,
         spdk_nvme_cmd,
     },
@@ -97,6 +104,72 @@ where
         self.io_complete(IoStatus::NoMemory);
     }
 
+    /// Marks the IO as failed with the given NVMe status.
+    ///
+    /// # Arguments
+    ///
+    /// * `status`: NVMe status to report.
+    #[inline]
+    pub fn fail_nvme_status(&self, status: NvmeStatus) {
+        self.complete_nvme_status(0, status);
+    }
+
+    /// Completes this I/O with the given NVMe completion status.
+    /// This allows a parent bdev to pass the status of a child NVMe I/O
+    /// upward exactly as received.
+    ///
+    /// # Arguments
+    ///
+    /// * `cdw0`: Command-specific dword 0 of the completion.
+    /// * `status`: NVMe status.
+    #[inline]
+    pub fn complete_nvme_status(&self, cdw0: u32, status: NvmeStatus) {
+        let (sct, sc) = status.as_sct_sc_codes();
+        unsafe {
+            spdk_bdev_io_complete_nvme_status(self.as_ptr(), cdw0, sct, sc);
+        }
+    }
+
+    /// Completes this I/O with the given SCSI status and sense data.
+    ///
+    /// # Arguments
+    ///
+    /// * `status`: SCSI status code (e.g. 0x02 for CHECK CONDITION).
+    /// * `sense_key`: Sense key.
+    /// * `asc`: Additional sense code.
+    /// * `ascq`: Additional sense code qualifier.
+    #[inline]
+    pub fn complete_scsi_status(
+        &self,
+        status: u8,
+        sense_key: u8,
+        asc: u8,
+        ascq: u8,
+    ) {
+        unsafe {
+            spdk_bdev_io_complete_scsi_status(
+                self.as_ptr(),
+                status as _,
+                sense_key as _,
+                asc,
+                ascq,
+            );
+        }
+    }
+
+    /// Completes this I/O with the given AIO result.
+    /// `Errno::UnknownErrno` (zero) means success.
+    ///
+    /// # Arguments
+    ///
+    /// * `errno`: Error number of the failed AIO request.
+    #[inline]
+    pub fn complete_aio_status(&self, errno: Errno) {
+        unsafe {
+            spdk_bdev_io_complete_aio_status(self.as_ptr(), -(errno as i32));
+        }
+    }
+
     /// TODO
     ///
     /// # Arguments
@@ -145,6 +218,20 @@ where
         unsafe { self.as_ref().u.bdev.num_blocks }
     }
 
+    /// Sets the result of a `IoType::SeekHole` or `IoType::SeekData` I/O:
+    /// the offset of the first hole or data block found.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset_blocks`: Offset of the block found.
+    #[inline]
+    pub fn set_seek_offset(&self, offset_blocks: u64) {
+        unsafe {
+            (*self.as_ptr()).u.bdev.__bindgen_anon_1.seek.offset =
+                offset_blocks;
+        }
+    }
+
     /// TODO
     #[inline]
     pub fn status(&self) -> IoStatus {
@@ -205,6 +292,60 @@ where
         NvmeStatus::from(self.as_ptr())
     }
 
+    /// Returns command-specific dword 0 of the NVMe completion of this I/O.
+    #[inline]
+    pub fn nvme_cdw0(&self) -> u32 {
+        let mut cdw0: u32 = 0;
+        let mut sct: i32 = 0;
+        let mut sc: i32 = 0;
+
+        unsafe {
+            spdk_bdev_io_get_nvme_status(
+                self.as_ptr(),
+                &mut cdw0,
+                &mut sct,
+                &mut sc,
+            );
+        }
+
+        cdw0
+    }
+
+    /// Returns the SCSI status of this I/O as a tuple of status code,
+    /// sense key, additional sense code and its qualifier.
+    #[inline]
+    pub fn scsi_status(&self) -> (u8, u8, u8, u8) {
+        let mut sc = 0;
+        let mut sk = 0;
+        let mut asc = 0;
+        let mut ascq = 0;
+
+        unsafe {
+            spdk_bdev_io_get_scsi_status(
+                self.as_ptr(),
+                &mut sc,
+                &mut sk,
+                &mut asc,
+                &mut ascq,
+            );
+        }
+
+        (sc as u8, sk as u8, asc as u8, ascq as u8)
+    }
+
+    /// Returns the AIO result of this I/O as an errno.
+    /// `Errno::UnknownErrno` (zero) means success.
+    #[inline]
+    pub fn aio_status(&self) -> Errno {
+        let mut res: i32 = 0;
+
+        unsafe {
+            spdk_bdev_io_get_aio_status(self.as_ptr(), &mut res);
+        }
+
+        Errno::from_i32(res.abs())
+    }
+
     /// TODO
     /// NVMe passthru command
     #[inline]
@@ -228,7 +369,7 @@ where
 
     /// TODO
     #[inline]
-    fn as_ref(&self) -> &spdk_bdev_io {
+    pub(crate) fn as_ref(&self) -> &spdk_bdev_io {
         unsafe { self.inner.as_ref() }
     }
 
@@ -240,7 +381,7 @@ where
 
     /// TODO
     #[inline]
-    fn as_ptr(&self) -> *mut spdk_bdev_io {
+    pub(crate) fn as_ptr(&self) -> *mut spdk_bdev_io {
         self.inner.as_ptr()
     }
 
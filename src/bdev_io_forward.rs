@@ -0,0 +1,695 @@
+///! Forwarding of bdev I/Os to base bdevs, for stacked virtual bdevs.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem::{size_of, zeroed},
+    os::raw::c_void,
+    ptr::null_mut,
+};
+
+use nix::errno::Errno;
+
+use crate::{
+    libspdk::{
+        spdk_bdev,
+        spdk_bdev_abort,
+        spdk_bdev_comparev_and_writev_blocks,
+        spdk_bdev_comparev_blocks_with_md,
+        spdk_bdev_copy_blocks,
+        spdk_bdev_ext_io_opts,
+        spdk_bdev_flush_blocks,
+        spdk_bdev_free_io,
+        spdk_bdev_get_zone_info,
+        spdk_bdev_io,
+        spdk_bdev_io_complete_base_io_status,
+        spdk_bdev_io_get_append_location,
+        spdk_bdev_io_get_seek_offset,
+        spdk_bdev_nvme_admin_passthru,
+        spdk_bdev_nvme_io_passthru,
+        spdk_bdev_nvme_io_passthru_md,
+        spdk_bdev_readv_blocks_ext,
+        spdk_bdev_reset,
+        spdk_bdev_seek_data,
+        spdk_bdev_seek_hole,
+        spdk_bdev_unmap_blocks,
+        spdk_bdev_write_zeroes_blocks,
+        spdk_bdev_writev_blocks_ext,
+        spdk_bdev_zcopy_end,
+        spdk_bdev_zcopy_start,
+        spdk_bdev_zone_appendv_with_md,
+        spdk_bdev_zone_info,
+        spdk_bdev_zone_management,
+    },
+    BdevDesc,
+    BdevDescError,
+    BdevIo,
+    BdevOps,
+    IoChannel,
+    IoType,
+    ZcopyPhase,
+};
+
+thread_local! {
+    /// Contexts of the I/Os forwarded from this thread and not completed
+    /// yet, by parent I/O address.
+    static FORWARDED: RefCell<HashMap<usize, usize>> =
+        RefCell::new(HashMap::new());
+
+    /// Child I/Os lending their buffers to the zero-copy I/Os forwarded
+    /// from this thread, by parent I/O address. A child is kept from
+    /// the start of the parent until its end.
+    static ZCOPY_CHILDREN: RefCell<HashMap<usize, usize>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Hook called when a forwarded child I/O completes, before the parent I/O
+/// is completed. It is given the parent I/O, the child I/O and the child's
+/// success flag. If the hook returns true, the child's status is used to
+/// complete the parent; otherwise, the child's status is ignored, and if
+/// no other child of the parent is left, the hook is responsible for
+/// completing the parent (e.g. by forwarding it again).
+/// The child I/O must not be used once the hook returns.
+pub type ForwardHook<ParentData, ChildData> =
+    Box<dyn FnOnce(BdevIo<ParentData>, BdevIo<ChildData>, bool) -> bool>;
+
+/// Completion hook of a child I/O, with its parent and child types erased.
+struct ChildHook {
+    /// Boxed `ForwardHook`.
+    hook: *mut c_void,
+    /// Calls and frees the hook.
+    call: unsafe fn(
+        *mut c_void,
+        *mut spdk_bdev_io,
+        *mut spdk_bdev_io,
+        bool,
+    ) -> bool,
+    /// Frees the hook without calling it.
+    free: unsafe fn(*mut c_void),
+}
+
+impl ChildHook {
+    /// Erases the types of a hook.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook`: Hook to erase.
+    fn new<ParentData, ChildData>(
+        hook: ForwardHook<ParentData, ChildData>,
+    ) -> Self
+    where
+        ParentData: BdevOps,
+        ChildData: BdevOps,
+    {
+        Self {
+            hook: Box::into_raw(Box::new(hook)) as *mut c_void,
+            call: call_hook::<ParentData, ChildData>,
+            free: free_hook::<ParentData, ChildData>,
+        }
+    }
+}
+
+/// Calls and frees an erased `ForwardHook`.
+///
+/// # Arguments
+///
+/// * `hook`: Boxed hook.
+/// * `parent`: Parent I/O.
+/// * `child`: Completed child I/O.
+/// * `success`: True if the child I/O has succeeded.
+unsafe fn call_hook<ParentData, ChildData>(
+    hook: *mut c_void,
+    parent: *mut spdk_bdev_io,
+    child: *mut spdk_bdev_io,
+    success: bool,
+) -> bool
+where
+    ParentData: BdevOps,
+    ChildData: BdevOps,
+{
+    let hook = Box::from_raw(hook as *mut ForwardHook<ParentData, ChildData>);
+    hook(BdevIo::from_ptr(parent), BdevIo::from_ptr(child), success)
+}
+
+/// Frees an erased `ForwardHook` without calling it.
+///
+/// # Arguments
+///
+/// * `hook`: Boxed hook.
+unsafe fn free_hook<ParentData, ChildData>(hook: *mut c_void)
+where
+    ParentData: BdevOps,
+    ChildData: BdevOps,
+{
+    drop(Box::from_raw(
+        hook as *mut ForwardHook<ParentData, ChildData>,
+    ));
+}
+
+/// Outstanding child of a forwarded I/O.
+struct ForwardChild {
+    /// Base bdev the child has been submitted to.
+    bdev: *mut spdk_bdev,
+    /// Optional completion hook.
+    hook: Option<ChildHook>,
+}
+
+/// Context of a forwarded I/O, shared by all its children: it is their
+/// callback argument, so that aborting the parent aborts all of them.
+struct ForwardCtx {
+    /// Parent I/O.
+    parent: *mut spdk_bdev_io,
+    /// Outstanding children.
+    children: Vec<ForwardChild>,
+    /// Completed child whose status completes the parent: the first failed
+    /// one, or else the last one. Kept until the parent completes, along
+    /// with its success flag and whether it lends zero-copy buffers.
+    status: Option<(*mut spdk_bdev_io, bool, bool)>,
+}
+
+impl<BdevData> BdevIo<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Submits the equivalent of this I/O to a base bdev, and completes
+    /// this I/O with the status of the child I/O (including NVMe, SCSI and
+    /// AIO statuses) once it finishes.
+    ///
+    /// An I/O can be forwarded to several base bdevs, e.g. by a mirror, but
+    /// to a base bdev only once at a time. It is then completed once all
+    /// children finish, with the status of the first failed child, or else
+    /// of the last one.
+    ///
+    /// For reads, the data buffer of this I/O must be already allocated
+    /// (see `need_buf()` and `alloc_buffer()`).
+    ///
+    /// If an error is returned, the child I/O has not been submitted, and
+    /// this I/O is not completed. On `Errno::ENOMEM`, the submission can be
+    /// retried from a `BdevDesc::queue_io_wait()` callback.
+    ///
+    /// All I/O types are forwarded, with the following specifics:
+    /// * All children of this I/O share a callback argument, so that an
+    ///   `IoType::Abort` I/O is forwarded as an abort of the children of the
+    ///   I/O to abort.
+    /// * A zero-copy I/O lends the buffers of its child from its start to its
+    ///   end, and both phases must be forwarded from the same thread.
+    /// * `IoType::Invalid` and `IoType::IoNumTypes` are not actual I/O types,
+    ///   and fail with `Errno::EINVAL`.
+    ///
+    /// # Arguments
+    ///
+    /// * `desc`: Descriptor of the base bdev.
+    /// * `chan`: I/O channel of the base bdev for the current thread.
+    pub fn forward<ChildData>(
+        &self,
+        desc: &BdevDesc<ChildData>,
+        chan: &IoChannel<ChildData::ChannelData>,
+    ) -> Result<(), BdevDescError>
+    where
+        ChildData: BdevOps,
+    {
+        self.forward_impl(desc, chan, None)
+    }
+
+    /// Same as `forward()`, but calls the given hook when the child I/O
+    /// completes. See `ForwardHook` for details.
+    ///
+    /// # Arguments
+    ///
+    /// * `desc`: Descriptor of the base bdev.
+    /// * `chan`: I/O channel of the base bdev for the current thread.
+    /// * `hook`: Completion hook.
+    pub fn forward_with_hook<ChildData, F>(
+        &self,
+        desc: &BdevDesc<ChildData>,
+        chan: &IoChannel<ChildData::ChannelData>,
+        hook: F,
+    ) -> Result<(), BdevDescError>
+    where
+        ChildData: BdevOps,
+        F: FnOnce(BdevIo<BdevData>, BdevIo<ChildData>, bool) -> bool + 'static,
+    {
+        self.forward_impl(desc, chan, Some(Box::new(hook)))
+    }
+
+    /// Completes this I/O with the status of another I/O, e.g. a child I/O
+    /// submitted to a base bdev. NVMe, SCSI and AIO statuses are passed
+    /// exactly as received.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_io`: I/O to take the status from.
+    pub fn complete_base_io_status<ChildData>(
+        &self,
+        base_io: &BdevIo<ChildData>,
+    ) where
+        ChildData: BdevOps,
+    {
+        unsafe {
+            spdk_bdev_io_complete_base_io_status(
+                self.as_ptr(),
+                base_io.as_ptr(),
+            );
+        }
+    }
+
+    /// Submits the child I/O.
+    ///
+    /// # Arguments
+    ///
+    /// * `desc`: Descriptor of the base bdev.
+    /// * `chan`: I/O channel of the base bdev for the current thread.
+    /// * `hook`: Optional completion hook.
+    fn forward_impl<ChildData>(
+        &self,
+        desc: &BdevDesc<ChildData>,
+        chan: &IoChannel<ChildData::ChannelData>,
+        hook: Option<ForwardHook<BdevData, ChildData>>,
+    ) -> Result<(), BdevDescError>
+    where
+        ChildData: BdevOps,
+    {
+        let io_type = self.io_type();
+
+        let submit_err = |e: Errno| BdevDescError::IoSubmit {
+            source: e,
+            io_type,
+            bdev_name: desc.bdev().name().to_owned(),
+        };
+
+        if io_type == IoType::Read && self.need_buf() {
+            return Err(submit_err(Errno::EINVAL));
+        }
+
+        let base = desc.bdev().as_inner_ptr();
+        let ctx = forward_ctx(self.as_ptr());
+
+        if unsafe { (*ctx).children.iter().any(|c| c.bdev == base) } {
+            release_forward_ctx(ctx);
+            return Err(submit_err(Errno::EBUSY));
+        }
+
+        let arg = ctx as *mut c_void;
+        let cb = Some(
+            inner_forward_cb
+                as unsafe extern "C" fn(*mut spdk_bdev_io, bool, *mut c_void),
+        );
+
+        let d = desc.as_ptr();
+        let ch = chan.legacy_as_ptr();
+        let bio = self.as_ref();
+
+        // The child of a zero-copy I/O being ended is the one that started.
+        let zcopy_child = match io_type {
+            IoType::ZeroCopy => match self.zcopy_phase() {
+                ZcopyPhase::Start {
+                    ..
+                } => null_mut(),
+                ZcopyPhase::End {
+                    ..
+                } => match take_zcopy_child(self.as_ptr()) {
+                    Some(child) => child,
+                    None => {
+                        release_forward_ctx(ctx);
+                        return Err(submit_err(Errno::EINVAL));
+                    }
+                },
+            },
+            _ => null_mut(),
+        };
+
+        // Aborts the children of the I/O to abort, if it has been forwarded.
+        let abort_arg = match io_type {
+            IoType::Abort => {
+                let bio_to_abort = unsafe { bio.u.abort.bio_to_abort };
+                FORWARDED
+                    .with(|f| f.borrow().get(&(bio_to_abort as usize)).copied())
+                    .unwrap_or(bio_to_abort as usize)
+                    as *mut c_void
+            }
+            _ => null_mut(),
+        };
+
+        unsafe {
+            (*ctx).children.push(ForwardChild {
+                bdev: base,
+                hook: hook.map(ChildHook::new),
+            });
+        }
+
+        let rc = unsafe {
+            match io_type {
+                IoType::Read => spdk_bdev_readv_blocks_ext(
+                    d,
+                    ch,
+                    bio.u.bdev.iovs,
+                    bio.u.bdev.iovcnt,
+                    bio.u.bdev.offset_blocks,
+                    bio.u.bdev.num_blocks,
+                    cb,
+                    arg,
+                    &mut self.ext_io_opts(),
+                ),
+                IoType::Write => spdk_bdev_writev_blocks_ext(
+                    d,
+                    ch,
+                    bio.u.bdev.iovs,
+                    bio.u.bdev.iovcnt,
+                    bio.u.bdev.offset_blocks,
+                    bio.u.bdev.num_blocks,
+                    cb,
+                    arg,
+                    &mut self.ext_io_opts(),
+                ),
+                IoType::Compare => spdk_bdev_comparev_blocks_with_md(
+                    d,
+                    ch,
+                    bio.u.bdev.iovs,
+                    bio.u.bdev.iovcnt,
+                    bio.u.bdev.md_buf,
+                    bio.u.bdev.offset_blocks,
+                    bio.u.bdev.num_blocks,
+                    cb,
+                    arg,
+                ),
+                IoType::CompareAndWrite => {
+                    spdk_bdev_comparev_and_writev_blocks(
+                        d,
+                        ch,
+                        bio.u.bdev.iovs,
+                        bio.u.bdev.iovcnt,
+                        bio.u.bdev.fused_iovs,
+                        bio.u.bdev.fused_iovcnt,
+                        bio.u.bdev.offset_blocks,
+                        bio.u.bdev.num_blocks,
+                        cb,
+                        arg,
+                    )
+                }
+                IoType::Unmap => spdk_bdev_unmap_blocks(
+                    d,
+                    ch,
+                    bio.u.bdev.offset_blocks,
+                    bio.u.bdev.num_blocks,
+                    cb,
+                    arg,
+                ),
+                IoType::WriteZeros => spdk_bdev_write_zeroes_blocks(
+                    d,
+                    ch,
+                    bio.u.bdev.offset_blocks,
+                    bio.u.bdev.num_blocks,
+                    cb,
+                    arg,
+                ),
+                IoType::Flush => spdk_bdev_flush_blocks(
+                    d,
+                    ch,
+                    bio.u.bdev.offset_blocks,
+                    bio.u.bdev.num_blocks,
+                    cb,
+                    arg,
+                ),
+                IoType::Copy => spdk_bdev_copy_blocks(
+                    d,
+                    ch,
+                    bio.u.bdev.offset_blocks,
+                    bio.u.bdev.__bindgen_anon_1.copy.src_offset_blocks,
+                    bio.u.bdev.num_blocks,
+                    cb,
+                    arg,
+                ),
+                IoType::Reset => spdk_bdev_reset(d, ch, cb, arg),
+                IoType::NvmeAdmin => spdk_bdev_nvme_admin_passthru(
+                    d,
+                    ch,
+                    &bio.u.nvme_passthru.cmd,
+                    bio.u.nvme_passthru.buf,
+                    bio.u.nvme_passthru.nbytes,
+                    cb,
+                    arg,
+                ),
+                IoType::NvmeIo => spdk_bdev_nvme_io_passthru(
+                    d,
+                    ch,
+                    &bio.u.nvme_passthru.cmd,
+                    bio.u.nvme_passthru.buf,
+                    bio.u.nvme_passthru.nbytes,
+                    cb,
+                    arg,
+                ),
+                IoType::NvmeIoMd => spdk_bdev_nvme_io_passthru_md(
+                    d,
+                    ch,
+                    &bio.u.nvme_passthru.cmd,
+                    bio.u.nvme_passthru.buf,
+                    bio.u.nvme_passthru.nbytes,
+                    bio.u.nvme_passthru.md_buf,
+                    bio.u.nvme_passthru.md_len,
+                    cb,
+                    arg,
+                ),
+                IoType::ZoneInfo => spdk_bdev_get_zone_info(
+                    d,
+                    ch,
+                    bio.u.zone_mgmt.zone_id,
+                    bio.u.zone_mgmt.num_zones as _,
+                    bio.u.zone_mgmt.buf as *mut spdk_bdev_zone_info,
+                    cb,
+                    arg,
+                ),
+                IoType::ZoneManagement => spdk_bdev_zone_management(
+                    d,
+                    ch,
+                    bio.u.zone_mgmt.zone_id,
+                    bio.u.zone_mgmt.zone_action,
+                    cb,
+                    arg,
+                ),
+                IoType::ZoneAppend => spdk_bdev_zone_appendv_with_md(
+                    d,
+                    ch,
+                    bio.u.bdev.iovs,
+                    bio.u.bdev.iovcnt,
+                    bio.u.bdev.md_buf,
+                    bio.u.bdev.offset_blocks,
+                    bio.u.bdev.num_blocks,
+                    cb,
+                    arg,
+                ),
+                IoType::ZeroCopy => match self.zcopy_phase() {
+                    ZcopyPhase::Start {
+                        populate,
+                    } => spdk_bdev_zcopy_start(
+                        d,
+                        ch,
+                        null_mut(),
+                        0,
+                        bio.u.bdev.offset_blocks,
+                        bio.u.bdev.num_blocks,
+                        populate,
+                        cb,
+                        arg,
+                    ),
+                    ZcopyPhase::End {
+                        commit,
+                    } => spdk_bdev_zcopy_end(zcopy_child, commit, cb, arg),
+                },
+                IoType::Abort => spdk_bdev_abort(d, ch, abort_arg, cb, arg),
+                IoType::SeekHole => spdk_bdev_seek_hole(
+                    d,
+                    ch,
+                    bio.u.bdev.offset_blocks,
+                    cb,
+                    arg,
+                ),
+                IoType::SeekData => spdk_bdev_seek_data(
+                    d,
+                    ch,
+                    bio.u.bdev.offset_blocks,
+                    cb,
+                    arg,
+                ),
+                IoType::Invalid | IoType::IoNumTypes => -(Errno::EINVAL as i32),
+            }
+        };
+
+        if rc != 0 {
+            unsafe {
+                let children = &mut (*ctx).children;
+                if let Some(c) = children.pop() {
+                    if let Some(h) = c.hook {
+                        (h.free)(h.hook);
+                    }
+                }
+            }
+            release_forward_ctx(ctx);
+            if !zcopy_child.is_null() {
+                ZCOPY_CHILDREN.with(|z| {
+                    z.borrow_mut()
+                        .insert(self.as_ptr() as usize, zcopy_child as usize)
+                });
+            }
+            return Err(submit_err(Errno::from_i32(rc.abs())));
+        }
+
+        Ok(())
+    }
+
+    /// Makes extended I/O options for a child read or write, so that
+    /// separate metadata, the memory domain and the accel sequence of this
+    /// I/O are passed to the base bdev as they are.
+    fn ext_io_opts(&self) -> spdk_bdev_ext_io_opts {
+        let mut opts: spdk_bdev_ext_io_opts = unsafe { zeroed() };
+        opts.size = size_of::<spdk_bdev_ext_io_opts>() as _;
+        opts.metadata = self.md_buf();
+        opts.memory_domain = self
+            .memory_domain()
+            .map_or(null_mut(), |d| d.legacy_as_ptr());
+        opts.memory_domain_ctx = self.memory_domain_ctx();
+        opts.accel_sequence = self.accel_sequence();
+        opts
+    }
+}
+
+/// Returns the context of a forwarded I/O, creating it if the I/O has no
+/// outstanding children.
+///
+/// # Arguments
+///
+/// * `parent`: Parent I/O.
+fn forward_ctx(parent: *mut spdk_bdev_io) -> *mut ForwardCtx {
+    FORWARDED.with(|f| {
+        *f.borrow_mut().entry(parent as usize).or_insert_with(|| {
+            Box::into_raw(Box::new(ForwardCtx {
+                parent,
+                children: Vec::new(),
+                status: None,
+            })) as usize
+        })
+    }) as *mut ForwardCtx
+}
+
+/// Frees the context of a forwarded I/O if it has neither outstanding
+/// children nor a completed one.
+///
+/// # Arguments
+///
+/// * `ctx`: Forwarding context.
+fn release_forward_ctx(ctx: *mut ForwardCtx) {
+    unsafe {
+        if (*ctx).children.is_empty() && (*ctx).status.is_none() {
+            FORWARDED
+                .with(|f| f.borrow_mut().remove(&((*ctx).parent as usize)));
+            drop(Box::from_raw(ctx));
+        }
+    }
+}
+
+/// Removes the child I/O lending its buffers to a started zero-copy I/O.
+///
+/// # Arguments
+///
+/// * `parent`: Parent zero-copy I/O.
+fn take_zcopy_child(parent: *mut spdk_bdev_io) -> Option<*mut spdk_bdev_io> {
+    ZCOPY_CHILDREN
+        .with(|z| z.borrow_mut().remove(&(parent as usize)))
+        .map(|p| p as *mut spdk_bdev_io)
+}
+
+/// Completion callback for forwarded child I/Os.
+/// Once the last outstanding child completes, the parent is completed with
+/// the status of the child selected by `ForwardCtx::status`, if any.
+///
+/// # Arguments
+///
+/// * `child`: Completed child I/O.
+/// * `success`: True if the child I/O has succeeded.
+/// * `arg`: Forwarding context.
+unsafe extern "C" fn inner_forward_cb(
+    child: *mut spdk_bdev_io,
+    success: bool,
+    arg: *mut c_void,
+) {
+    let ctx = arg as *mut ForwardCtx;
+    let parent = BdevIo::<()>::from_ptr((*ctx).parent);
+    let io_type = parent.io_type();
+
+    let hook = {
+        let children = &mut (*ctx).children;
+        match children.iter().position(|c| c.bdev == (*child).bdev) {
+            Some(i) => children.remove(i).hook,
+            None => None,
+        }
+    };
+
+    // A started zero-copy child is kept until the parent ends, and the
+    // parent uses its buffers.
+    let keep_child = success
+        && io_type == IoType::ZeroCopy
+        && matches!(parent.zcopy_phase(), ZcopyPhase::Start { .. });
+
+    if keep_child {
+        (*parent.as_ptr()).u.bdev.iovs = (*child).u.bdev.iovs;
+        (*parent.as_ptr()).u.bdev.iovcnt = (*child).u.bdev.iovcnt;
+        ZCOPY_CHILDREN.with(|z| {
+            z.borrow_mut()
+                .insert(parent.as_ptr() as usize, child as usize)
+        });
+    }
+
+    // The hook may forward the parent again, adding a new child.
+    let use_status = match hook {
+        Some(h) => (h.call)(h.hook, parent.as_ptr(), child, success),
+        None => true,
+    };
+
+    let mut free_child = !keep_child;
+
+    if use_status {
+        if success {
+            match io_type {
+                IoType::ZoneAppend => parent.set_append_location(
+                    spdk_bdev_io_get_append_location(child),
+                ),
+                IoType::SeekHole | IoType::SeekData => {
+                    parent.set_seek_offset(spdk_bdev_io_get_seek_offset(child))
+                }
+                _ => {}
+            }
+        }
+
+        // Keep the first failed child, or else the last one.
+        let replace = match (*ctx).status {
+            Some((_, ok, _)) => ok,
+            None => true,
+        };
+        if replace {
+            if let Some((prev, _, kept)) =
+                (*ctx).status.replace((child, success, keep_child))
+            {
+                if !kept {
+                    spdk_bdev_free_io(prev);
+                }
+            }
+            free_child = false;
+        }
+    }
+
+    if free_child {
+        spdk_bdev_free_io(child);
+    }
+
+    if !(*ctx).children.is_empty() {
+        return;
+    }
+
+    FORWARDED.with(|f| f.borrow_mut().remove(&((*ctx).parent as usize)));
+    let ctx = Box::from_raw(ctx);
+
+    if let Some((status_child, _, kept)) = ctx.status {
+        parent.complete_base_io_status(&BdevIo::<()>::from_ptr(status_child));
+        if !kept {
+            spdk_bdev_free_io(status_child);
+        }
+    }
+}
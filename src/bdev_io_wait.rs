@@ -0,0 +1,111 @@
+///! Waiting for bdev I/O resources via `spdk_bdev_queue_io_wait`.
+use std::os::raw::c_void;
+
+use futures::channel::oneshot;
+use nix::errno::Errno;
+
+use crate::{
+    libspdk::{spdk_bdev_io_wait_entry, spdk_bdev_queue_io_wait},
+    BdevDesc,
+    BdevDescError,
+    BdevOps,
+    IoChannel,
+};
+
+/// Heap-allocated SPDK I/O wait entry together with the callback to run
+/// once bdev I/O resources become available.
+/// SPDK keeps a pointer to the entry until the callback is called, so the
+/// entry is leaked on queueing and reclaimed in the callback.
+#[repr(C)]
+struct IoWaitEntry {
+    /// SPDK wait entry. Must be the first field.
+    entry: spdk_bdev_io_wait_entry,
+    /// Callback to run.
+    cb: Box<dyn FnOnce()>,
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Queues a callback to be called once a bdev I/O becomes available on
+    /// the given channel. This must be used only right after an I/O
+    /// submission to this descriptor's bdev has failed with
+    /// `Errno::ENOMEM`, typically to resubmit the I/O from the callback.
+    ///
+    /// The callback is called on the current thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel on which the submission has failed.
+    /// * `cb`: Callback to call.
+    pub fn queue_io_wait<F>(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cb: F,
+    ) -> Result<(), BdevDescError>
+    where
+        F: FnOnce() + 'static,
+    {
+        let w = Box::into_raw(Box::new(IoWaitEntry {
+            entry: spdk_bdev_io_wait_entry {
+                bdev: self.bdev().as_inner_ptr(),
+                cb_fn: Some(inner_io_wait_cb),
+                cb_arg: std::ptr::null_mut(),
+                link: unsafe { std::mem::zeroed() },
+            },
+            cb: Box::new(cb),
+        }));
+
+        let rc = unsafe {
+            (*w).entry.cb_arg = w as *mut c_void;
+            spdk_bdev_queue_io_wait(
+                (*w).entry.bdev,
+                chan.legacy_as_ptr(),
+                &mut (*w).entry,
+            )
+        };
+
+        if rc != 0 {
+            unsafe { drop(Box::from_raw(w)) };
+            return Err(BdevDescError::IoWait {
+                source: Errno::from_i32(rc.abs()),
+                bdev_name: self.bdev().name().to_owned(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Waits until a bdev I/O becomes available on the given channel.
+    /// This must be used only right after an I/O submission to this
+    /// descriptor's bdev has failed with `Errno::ENOMEM`; the I/O can be
+    /// resubmitted once the future completes.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel on which the submission has failed.
+    pub async fn wait_for_io_resources(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+    ) -> Result<(), BdevDescError> {
+        let (s, r) = oneshot::channel::<()>();
+
+        self.queue_io_wait(chan, move || {
+            s.send(()).ok();
+        })?;
+
+        r.await.expect("I/O wait sender is gone");
+        Ok(())
+    }
+}
+
+/// Called by SPDK when a bdev I/O becomes available.
+///
+/// # Arguments
+///
+/// * `arg`: Pointer to the `IoWaitEntry`.
+unsafe extern "C" fn inner_io_wait_cb(arg: *mut c_void) {
+    let w = Box::from_raw(arg as *mut IoWaitEntry);
+    (w.cb)();
+}
@@ -1,10 +1,25 @@
-use std::{ffi::CString, marker::PhantomData, mem::zeroed, ptr::NonNull};
+use std::{
+    any::TypeId,
+    ffi::CString,
+    marker::PhantomData,
+    mem::zeroed,
+    ptr::NonNull,
+};
+
+use async_trait::async_trait;
+use nix::errno::Errno;
+use parking_lot::{const_mutex, Mutex};
 
 use crate::{
     ffihelper::{AsStr, IntoCString},
     libspdk::{
+        spdk_bdev,
         spdk_bdev_module,
         spdk_bdev_module_claim_bdev,
+        spdk_bdev_module_examine_done,
+        spdk_bdev_module_fini_done,
+        spdk_bdev_module_fini_start_done,
+        spdk_bdev_module_init_done,
         spdk_bdev_module_list_add,
         spdk_bdev_module_list_find,
         spdk_json_write_ctx,
@@ -17,8 +32,24 @@ use crate::{
     JsonWriteContext,
     SpdkError,
     SpdkResult,
+    Thread,
+    UntypedBdev,
 };
 
+/// A Bdev module registered via `BdevModuleBuilder`.
+struct ModuleEntry {
+    /// Module type.
+    module_type: TypeId,
+    /// Address of the SPDK module structure. Module structures are never
+    /// freed, so their addresses stay valid.
+    ptr: usize,
+    /// Data types of the Bdevs built by `BdevBuilder` for this module.
+    bdev_data_types: Vec<TypeId>,
+}
+
+/// SPDK Bdev modules registered via `BdevModuleBuilder`.
+static REGISTERED_MODULES: Mutex<Vec<ModuleEntry>> = const_mutex(Vec::new());
+
 /// Wrapper for SPDK Bdev module structure.
 pub struct BdevModule {
     /// Pointer to SPDK Bdev module structure.
@@ -186,6 +217,71 @@ pub trait WithModuleConfigJson {
     fn config_json(w: JsonWriteContext) -> i32;
 }
 
+/// Bdev module has to implement this trait in order to enable an
+/// asynchronous module initialization callback. Module initialization
+/// completes when the returned future resolves.
+#[async_trait(?Send)]
+pub trait WithModuleAsyncInit {
+    /// Initializes the module.
+    async fn module_init();
+}
+
+/// Bdev module has to implement this trait in order to enable an
+/// asynchronous module shutdown callback. Module shutdown completes when
+/// the returned future resolves.
+#[async_trait(?Send)]
+pub trait WithModuleAsyncFini {
+    /// Shuts down the module.
+    async fn module_fini();
+}
+
+/// Bdev module has to implement this trait in order to enable a callback
+/// called once all Bdev modules have been initialized.
+pub trait WithModuleInitComplete {
+    /// Called once all Bdev modules have been initialized.
+    fn module_init_complete();
+}
+
+/// Bdev module has to implement this trait in order to enable a callback
+/// called when the Bdev layer starts shutting down, before Bdevs are
+/// unregistered.
+pub trait WithModuleFiniStart {
+    /// Called when the Bdev layer starts shutting down.
+    fn module_fini_start();
+}
+
+/// Bdev module has to implement this trait in order to enable an
+/// asynchronous variant of `WithModuleFiniStart`. Unregistration of Bdevs
+/// waits until the returned future resolves.
+#[async_trait(?Send)]
+pub trait WithModuleAsyncFiniStart {
+    /// Called when the Bdev layer starts shutting down.
+    async fn module_fini_start();
+}
+
+/// Bdev module has to implement this trait in order to examine new Bdevs
+/// as they are registered, e.g. to create virtual Bdevs on top of them.
+#[async_trait(?Send)]
+pub trait WithModuleExamine {
+    /// Called for each new Bdev before any module can open it, so that the
+    /// module can claim it according to its configuration. Must not submit
+    /// I/Os. Does nothing by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `bdev`: New Bdev.
+    fn examine_config(_bdev: UntypedBdev) {}
+
+    /// Called for each new Bdev once all modules have examined its
+    /// configuration. The module can open the Bdev and read it here, e.g.
+    /// to look for on-disk metadata. Does nothing by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `bdev`: New Bdev.
+    async fn examine_disk(_bdev: UntypedBdev) {}
+}
+
 /// Called by SPDK during module initialization.
 ///
 /// # Safety
@@ -240,6 +336,172 @@ where
     M::config_json(JsonWriteContext::from_ptr(w))
 }
 
+/// Called by SPDK during asynchronous module initialization.
+/// Reports completion to SPDK when the module's future resolves.
+unsafe extern "C" fn inner_module_async_init<M>() -> i32
+where
+    M: WithModuleAsyncInit + 'static,
+{
+    // Failing synchronously makes SPDK abort the initialization instead of
+    // waiting for the module to be done.
+    if spawn_module_task(async {
+        M::module_init().await;
+        spdk_bdev_module_init_done(registered_module_ptr::<M>());
+    }) {
+        0
+    } else {
+        -(Errno::EINVAL as i32)
+    }
+}
+
+/// Called by SPDK during asynchronous module shutdown.
+/// Reports completion to SPDK when the module's future resolves.
+unsafe extern "C" fn inner_module_async_fini<M>()
+where
+    M: WithModuleAsyncFini,
+{
+    if !spawn_module_task(async {
+        M::module_fini().await;
+        spdk_bdev_module_fini_done();
+    }) {
+        spdk_bdev_module_fini_done();
+    }
+}
+
+/// Called by SPDK once all Bdev modules have been initialized.
+unsafe extern "C" fn inner_module_init_complete<M>()
+where
+    M: WithModuleInitComplete,
+{
+    M::module_init_complete()
+}
+
+/// Called by SPDK when the Bdev layer starts shutting down.
+unsafe extern "C" fn inner_module_fini_start<M>()
+where
+    M: WithModuleFiniStart,
+{
+    M::module_fini_start()
+}
+
+/// Called by SPDK when the Bdev layer starts shutting down, for modules
+/// with an asynchronous `fini_start`.
+/// Reports completion to SPDK when the module's future resolves.
+unsafe extern "C" fn inner_module_async_fini_start<M>()
+where
+    M: WithModuleAsyncFiniStart,
+{
+    if !spawn_module_task(async {
+        M::module_fini_start().await;
+        spdk_bdev_module_fini_start_done();
+    }) {
+        spdk_bdev_module_fini_start_done();
+    }
+}
+
+/// Called by SPDK to examine the configuration of a new Bdev.
+///
+/// # Arguments
+///
+/// * `bdev`: New Bdev.
+unsafe extern "C" fn inner_examine_config<M>(bdev: *mut spdk_bdev)
+where
+    M: WithModuleExamine + 'static,
+{
+    M::examine_config(UntypedBdev::from_inner_ptr(bdev));
+    spdk_bdev_module_examine_done(registered_module_ptr::<M>());
+}
+
+/// Called by SPDK to examine the contents of a new Bdev.
+/// Reports completion to SPDK when the module's future resolves.
+///
+/// # Arguments
+///
+/// * `bdev`: New Bdev.
+unsafe extern "C" fn inner_examine_disk<M>(bdev: *mut spdk_bdev)
+where
+    M: WithModuleExamine + 'static,
+{
+    let bdev = UntypedBdev::from_inner_ptr(bdev);
+    if !spawn_module_task(async move {
+        M::examine_disk(bdev).await;
+        spdk_bdev_module_examine_done(registered_module_ptr::<M>());
+    }) {
+        spdk_bdev_module_examine_done(registered_module_ptr::<M>());
+    }
+}
+
+/// Spawns a module lifecycle task on the current SPDK thread.
+/// Returns false if not called on an SPDK thread, in which case the task
+/// is dropped and the caller must report completion to SPDK itself.
+///
+/// # Arguments
+///
+/// * `fut`: Future to run.
+fn spawn_module_task<F>(fut: F) -> bool
+where
+    F: std::future::Future<Output = ()> + 'static,
+{
+    match Thread::current() {
+        Some(thread) => {
+            thread.spawn_local(fut);
+            true
+        }
+        None => {
+            error!("Bdev module callback called outside of an SPDK thread");
+            false
+        }
+    }
+}
+
+/// Records that a module registered via `BdevModuleBuilder` builds Bdevs
+/// with the given data type. Does nothing for other modules.
+///
+/// # Arguments
+///
+/// * `ptr`: Pointer to the SPDK module structure.
+/// * `data_type`: Type of the Bdev data.
+pub(crate) fn add_module_bdev_data_type(
+    ptr: *const spdk_bdev_module,
+    data_type: TypeId,
+) {
+    let mut modules = REGISTERED_MODULES.lock();
+
+    if let Some(m) = modules.iter_mut().find(|m| m.ptr == ptr as usize) {
+        if !m.bdev_data_types.contains(&data_type) {
+            m.bdev_data_types.push(data_type);
+        }
+    }
+}
+
+/// Determines if a module has been registered via `BdevModuleBuilder`,
+/// and builds Bdevs with the given data type.
+///
+/// # Arguments
+///
+/// * `ptr`: Pointer to the SPDK module structure.
+/// * `data_type`: Type of the Bdev data.
+pub(crate) fn module_has_bdev_data_type(
+    ptr: *const spdk_bdev_module,
+    data_type: TypeId,
+) -> bool {
+    REGISTERED_MODULES.lock().iter().any(|m| {
+        m.ptr == ptr as usize && m.bdev_data_types.contains(&data_type)
+    })
+}
+
+/// Returns a pointer to the SPDK structure of a module registered via
+/// `BdevModuleBuilder`.
+fn registered_module_ptr<M>() -> *mut spdk_bdev_module
+where
+    M: ?Sized + 'static,
+{
+    RegisteredModule::<M>::get()
+        .expect("Bdev module must be registered")
+        .module()
+        .as_ptr()
+}
+
 /// Bdev module configuration builder.
 pub struct BdevModuleBuilder<M: ?Sized> {
     /// TODO
@@ -252,6 +514,20 @@ pub struct BdevModuleBuilder<M: ?Sized> {
     get_ctx_size: Option<unsafe extern "C" fn() -> i32>,
     /// TODO
     config_json: Option<unsafe extern "C" fn(*mut spdk_json_write_ctx) -> i32>,
+    /// Called once all Bdev modules have been initialized.
+    init_complete: Option<unsafe extern "C" fn()>,
+    /// Called when the Bdev layer starts shutting down.
+    fini_start: Option<unsafe extern "C" fn()>,
+    /// Examines the configuration of new Bdevs.
+    examine_config: Option<unsafe extern "C" fn(*mut spdk_bdev)>,
+    /// Examines the contents of new Bdevs.
+    examine_disk: Option<unsafe extern "C" fn(*mut spdk_bdev)>,
+    /// Module initialization completes asynchronously.
+    async_init: bool,
+    /// Module shutdown completes asynchronously.
+    async_fini: bool,
+    /// Shutdown start completes asynchronously.
+    async_fini_start: bool,
     /// TODO
     _module: PhantomData<M>,
 }
@@ -263,6 +539,7 @@ where
     /// TODO
     pub fn with_module_init(mut self) -> Self {
         self.module_init = Some(inner_module_init::<M>);
+        self.async_init = false;
         self
     }
 }
@@ -274,6 +551,7 @@ where
     /// TODO
     pub fn with_module_fini(mut self) -> Self {
         self.module_fini = Some(inner_module_fini::<M>);
+        self.async_fini = false;
         self
     }
 }
@@ -299,6 +577,80 @@ where
     }
 }
 
+impl<M> BdevModuleBuilder<M>
+where
+    M: WithModuleAsyncInit + 'static,
+{
+    /// Enables asynchronous module initialization. Replaces a callback set
+    /// by `with_module_init()`.
+    pub fn with_module_async_init(mut self) -> Self {
+        self.module_init = Some(inner_module_async_init::<M>);
+        self.async_init = true;
+        self
+    }
+}
+
+impl<M> BdevModuleBuilder<M>
+where
+    M: WithModuleAsyncFini,
+{
+    /// Enables asynchronous module shutdown. Replaces a callback set by
+    /// `with_module_fini()`.
+    pub fn with_module_async_fini(mut self) -> Self {
+        self.module_fini = Some(inner_module_async_fini::<M>);
+        self.async_fini = true;
+        self
+    }
+}
+
+impl<M> BdevModuleBuilder<M>
+where
+    M: WithModuleInitComplete,
+{
+    /// Enables the initialization completion callback.
+    pub fn with_module_init_complete(mut self) -> Self {
+        self.init_complete = Some(inner_module_init_complete::<M>);
+        self
+    }
+}
+
+impl<M> BdevModuleBuilder<M>
+where
+    M: WithModuleFiniStart,
+{
+    /// Enables the shutdown start callback.
+    pub fn with_module_fini_start(mut self) -> Self {
+        self.fini_start = Some(inner_module_fini_start::<M>);
+        self.async_fini_start = false;
+        self
+    }
+}
+
+impl<M> BdevModuleBuilder<M>
+where
+    M: WithModuleAsyncFiniStart,
+{
+    /// Enables the asynchronous shutdown start callback. Replaces
+    /// a callback set by `with_module_fini_start()`.
+    pub fn with_module_async_fini_start(mut self) -> Self {
+        self.fini_start = Some(inner_module_async_fini_start::<M>);
+        self.async_fini_start = true;
+        self
+    }
+}
+
+impl<M> BdevModuleBuilder<M>
+where
+    M: WithModuleExamine + 'static,
+{
+    /// Enables examination of new Bdevs.
+    pub fn with_module_examine(mut self) -> Self {
+        self.examine_config = Some(inner_examine_config::<M>);
+        self.examine_disk = Some(inner_examine_disk::<M>);
+        self
+    }
+}
+
 /// TODO
 impl<M: ?Sized> BdevModuleBuilder<M> {
     /// TODO
@@ -313,31 +665,157 @@ impl<M: ?Sized> BdevModuleBuilder<M> {
             module_fini: None,
             get_ctx_size: None,
             config_json: None,
+            init_complete: None,
+            fini_start: None,
+            examine_config: None,
+            examine_disk: None,
+            async_init: false,
+            async_fini: false,
+            async_fini_start: false,
             _module: Default::default(),
         }
     }
 
     /// Consumes the builder, builds a new Bdev module inner representation,
-    /// and registers it within SPDK.
+    /// and registers it within SPDK. Returns a typed handle to the module.
     /// This new module can be later obtained via `find_by_name()` method
-    /// of `BdevModule`.
-    pub fn register(self) {
+    /// of `BdevModule`, or via `RegisteredModule::get()`.
+    ///
+    /// Fails if a module of the same type `M` has already been registered:
+    /// SPDK callbacks of a module find the module by its type.
+    pub fn register(self) -> SpdkResult<RegisteredModule<M>>
+    where
+        M: 'static,
+    {
+        let mut modules = REGISTERED_MODULES.lock();
+        let id = TypeId::of::<M>();
+
+        if modules.iter().any(|m| m.module_type == id) {
+            return Err(SpdkError::BdevModuleAlreadyRegistered {
+                name: String::from(std::any::type_name::<M>()),
+            });
+        }
+
         let inner = Box::new(spdk_bdev_module {
             module_init: self.module_init,
-            init_complete: None,
-            fini_start: None,
+            init_complete: self.init_complete,
+            fini_start: self.fini_start,
             module_fini: self.module_fini,
             config_json: self.config_json,
             name: self.name.into_raw(),
             get_ctx_size: self.get_ctx_size,
-            examine_config: None,
-            examine_disk: None,
-            async_init: false,
-            async_fini: false,
-            async_fini_start: false,
+            examine_config: self.examine_config,
+            examine_disk: self.examine_disk,
+            async_init: self.async_init,
+            async_fini: self.async_fini,
+            async_fini_start: self.async_fini_start,
             internal: unsafe { zeroed() },
         });
 
-        unsafe { spdk_bdev_module_list_add(Box::into_raw(inner)) }
+        let ptr = Box::into_raw(inner);
+        modules.push(ModuleEntry {
+            module_type: id,
+            ptr: ptr as usize,
+            bdev_data_types: Vec::new(),
+        });
+        drop(modules);
+
+        unsafe { spdk_bdev_module_list_add(ptr) }
+
+        Ok(RegisteredModule::from_ptr(ptr))
+    }
+}
+
+/// Bdev module has to implement this trait in order to get typed access
+/// to its Bdevs via `RegisteredModule`.
+pub trait WithModuleBdevData {
+    /// Data type of the module's Bdevs.
+    type BdevData: BdevOps<BdevData = Self::BdevData> + 'static;
+}
+
+/// Typed handle to a Bdev module registered via `BdevModuleBuilder`.
+/// Only Bdevs that belong to this module and have been created with the
+/// module's Bdev data type (see `WithModuleBdevData`) are handed out as
+/// typed Bdevs.
+pub struct RegisteredModule<M: ?Sized> {
+    /// Untyped module.
+    module: BdevModule,
+    /// Module type.
+    _module: PhantomData<M>,
+}
+
+impl<M: ?Sized + 'static> RegisteredModule<M> {
+    /// Returns the handle of a module registered with the given module
+    /// type, or `None` if no such module has been registered.
+    pub fn get() -> Option<Self> {
+        let id = TypeId::of::<M>();
+        REGISTERED_MODULES
+            .lock()
+            .iter()
+            .find(|m| m.module_type == id)
+            .map(|m| Self::from_ptr(m.ptr as *mut spdk_bdev_module))
+    }
+}
+
+impl<M: ?Sized> RegisteredModule<M> {
+    /// Returns the untyped module.
+    pub fn module(&self) -> &BdevModule {
+        &self.module
+    }
+
+    /// Returns module's name.
+    pub fn name(&self) -> &str {
+        self.module.name()
+    }
+
+    /// Makes a new handle from a pointer to a registered module.
+    ///
+    /// # Arguments
+    ///
+    /// * `ptr`: Pointer to the registered module.
+    fn from_ptr(ptr: *mut spdk_bdev_module) -> Self {
+        Self {
+            module: BdevModule::from_ptr(ptr),
+            _module: Default::default(),
+        }
+    }
+}
+
+impl<M> RegisteredModule<M>
+where
+    M: WithModuleBdevData + ?Sized,
+{
+    /// Returns a builder for a new Bdev of this module.
+    pub fn bdev_builder(&self) -> BdevBuilder<'_, M::BdevData> {
+        self.module.bdev_builder().with_data_type()
+    }
+
+    /// Returns an iterator over the Bdevs of this module that have been
+    /// created with the module's Bdev data type.
+    pub fn iter_bdevs(&self) -> impl Iterator<Item = Bdev<M::BdevData>> {
+        self.module
+            .iter_bdevs::<()>()
+            .filter_map(|b| b.try_downcast())
+    }
+
+    /// Looks up a Bdev of this module by its name. Returns `None` if there
+    /// is no such Bdev, or it belongs to another module, or it has been
+    /// created with another data type.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: Bdev name.
+    pub fn lookup_bdev(&self, name: &str) -> Option<Bdev<M::BdevData>> {
+        let b = UntypedBdev::lookup_by_name(name)?;
+        if b.module().as_ptr() != self.module.as_ptr() {
+            return None;
+        }
+        b.try_downcast()
+    }
+}
+
+impl<M: ?Sized> Clone for RegisteredModule<M> {
+    fn clone(&self) -> Self {
+        Self::from_ptr(self.module.as_ptr())
     }
 }
@@ -1,5 +1,12 @@
 ///! TODO
-use crate::{BdevIo, IoChannel, IoDevice, IoType, JsonWriteContext};
+use crate::{
+    BdevIo,
+    IoChannel,
+    IoDevice,
+    IoType,
+    JsonWriteContext,
+    MemoryDomain,
+};
 use std::pin::Pin;
 
 /// TODO
@@ -44,4 +51,116 @@ pub trait BdevOps {
     ///
     /// * `w`: TODO
     fn dump_info_json(&self, _w: JsonWriteContext) {}
+
+    /// Writes the configuration of the Bdev for SPDK `save_config` RPC,
+    /// typically an RPC call that recreates the Bdev when the configuration
+    /// is replayed (see `JsonWriteContext::write_rpc_call`).
+    /// Writes nothing by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `w`: JSON write context.
+    fn write_config_json(&self, _w: JsonWriteContext) {}
+
+    /// Writes driver-specific statistics of the Bdev for SPDK
+    /// `bdev_get_iostat` RPC. The output is placed next to the generic
+    /// statistics. Writes nothing by default.
+    ///
+    /// This is called synchronously on the RPC thread, so the channels
+    /// cannot be visited here. The per-channel statistics (see
+    /// `channel_stat`) are an asynchronous snapshot instead: the one
+    /// collected after the previous call, or `None` on the first call.
+    /// A new snapshot is collected once this returns, if
+    /// `has_channel_stat` returns true.
+    ///
+    /// # Arguments
+    ///
+    /// * `w`: JSON write context.
+    /// * `chan_stats`: Last snapshot of the statistics of the I/O channels, one
+    ///   entry per channel.
+    fn dump_device_stat_json(
+        &self,
+        _w: JsonWriteContext,
+        _chan_stats: Option<&[serde_json::Value]>,
+    ) {
+    }
+
+    /// Determines if the Bdev reports per-channel statistics via
+    /// `channel_stat`. Channels are not visited unless this returns true.
+    /// Returns false by default.
+    fn has_channel_stat(&self) -> bool {
+        false
+    }
+
+    /// Returns driver-specific statistics of an I/O channel, to be passed
+    /// to `dump_device_stat_json`. Called on the thread owning the channel,
+    /// if `has_channel_stat` returns true. Returns `None` by default, in
+    /// which case the channel is not reported.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: Channel data.
+    fn channel_stat(
+        &self,
+        _chan: &Self::ChannelData,
+    ) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Resets driver-specific statistics of the Bdev.
+    /// Called once, before `reset_channel_stat` is called for each I/O
+    /// channel. Does nothing by default.
+    fn reset_device_stat(&self) {}
+
+    /// Resets driver-specific statistics of an I/O channel.
+    /// Called on the thread owning the channel. Does nothing by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: Channel data.
+    fn reset_channel_stat(&self, _chan: &mut Self::ChannelData) {}
+
+    /// Returns the memory domains the Bdev can access directly. I/Os with
+    /// data buffers in these domains are submitted to the Bdev as is;
+    /// otherwise, the generic bdev layer bounces the data to local memory.
+    /// Returns no domains by default.
+    fn get_memory_domains(&self) -> Vec<MemoryDomain> {
+        Vec::new()
+    }
+
+    /// Determines if the Bdev supports accel sequences for the given I/O
+    /// type, i.e. can execute the accel operations attached to an I/O
+    /// itself. Not supported by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `io_type`: I/O type.
+    fn accel_sequence_supported(&self, _io_type: IoType) -> bool {
+        false
+    }
+
+    /// Returns the time the pollers of an I/O channel have spent spinning
+    /// since the last call, in microseconds, for SPDK `thread_get_stats`
+    /// RPC (see `SpinTracker`). Called on the thread owning the channel.
+    /// Returns zero by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: Channel data.
+    fn spin_time(_chan: &mut Self::ChannelData) -> u64 {
+        0
+    }
+}
+
+/// Bdev data has to implement this trait in order to provide
+/// a module-specific context of the Bdev, for SPDK `spdk_bdev_get_module_ctx`
+/// (see `BdevBuilder::with_module_ctx`). It lets other modules that know the
+/// Bdev's data type reach its module-specific state
+/// (see `BdevDesc::module_ctx`).
+pub trait WithBdevModuleCtx: BdevOps {
+    /// Type of the module-specific context of the Bdev.
+    type ModuleCtx: 'static;
+
+    /// Returns the module-specific context of the Bdev, if any.
+    fn get_module_ctx(&self) -> Option<&Self::ModuleCtx>;
 }
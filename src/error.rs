@@ -9,6 +9,9 @@ pub enum SpdkError {
     #[snafu(display("Bdev module '{name}' does not exist"))]
     BdevModuleNotFound { name: String },
 
+    #[snafu(display("Bdev module type '{name}' is already registered"))]
+    BdevModuleAlreadyRegistered { name: String },
+
     #[snafu(display("Bdev '{name}' is already claimed by another module"))]
     BdevAlreadyClaimed { name: String },
 
@@ -28,6 +31,12 @@ pub enum SpdkError {
 
     #[snafu(display("SPDK JSON write failed: error code {code}"))]
     JsonWriteFailed { code: i32 },
+
+    #[snafu(display("Bdev parameter '{param}' must be set"))]
+    BdevParamMissing { param: String },
+
+    #[snafu(display("Invalid parameters for Bdev '{name}': {reason}"))]
+    BdevParamsInvalid { name: String, reason: String },
 }
 
 /// TODO
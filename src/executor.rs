@@ -0,0 +1,232 @@
+//! Single-threaded future executor running on SPDK threads.
+//!
+//! A task spawned on an SPDK thread is always polled on that thread: waking
+//! a task sends an SPDK message to its thread via `spdk_thread_send_msg`,
+//! so futures never migrate between reactors.
+use std::{
+    cell::UnsafeCell,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    channel::oneshot::{self, Canceled, Receiver},
+    future::LocalBoxFuture,
+    task::{waker_ref, ArcWake},
+    FutureExt,
+};
+
+use crate::{
+    libspdk::spdk_thread_poll,
+    CurrentThreadGuard,
+    Thread,
+    UnsafeData,
+};
+
+/// Initial sleep time of `Thread::block_on()` once the thread is idle.
+const BLOCK_ON_MIN_BACKOFF: Duration = Duration::from_micros(1);
+
+/// Maximum sleep time of `Thread::block_on()` while the thread is idle.
+const BLOCK_ON_MAX_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Spawned task.
+struct Task {
+    /// SPDK thread this task belongs to.
+    thread: Thread,
+    /// The future being executed. It is only accessed on the task's thread,
+    /// and set to `None` once complete.
+    future: UnsafeCell<Option<LocalBoxFuture<'static, ()>>>,
+    /// Set when a message to poll this task has been sent.
+    scheduled: AtomicBool,
+}
+
+// `Task` can be woken from any thread, but its future is only accessed on the
+// task's own SPDK thread.
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+impl Task {
+    /// Sends a message to the task's thread to poll the task, unless it is
+    /// already scheduled.
+    fn schedule(self: &Arc<Self>) {
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            self.thread.send_msg(self.clone(), |t| t.run());
+        }
+    }
+
+    /// Polls the task's future. Must be called on the task's thread.
+    fn run(self: &Arc<Self>) {
+        debug_assert_eq!(Thread::current(), Some(self.thread));
+
+        self.scheduled.store(false, Ordering::Release);
+
+        let slot = unsafe { &mut *self.future.get() };
+        if let Some(fut) = slot.as_mut() {
+            let waker = waker_ref(self);
+            let mut cx = Context::from_waker(&waker);
+            if fut.as_mut().poll(&mut cx).is_ready() {
+                *slot = None;
+            }
+        }
+    }
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.schedule();
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        // An unfinished future may be dropped by the last waker on another
+        // thread: send it back to its own thread to be dropped there.
+        if let Some(fut) = self.future.get_mut().take() {
+            if Thread::current() == Some(self.thread) {
+                drop(fut);
+            } else {
+                self.thread.send_msg(UnsafeData::new(fut), drop);
+            }
+        }
+    }
+}
+
+/// Handle to a spawned task, which resolves to the task's output.
+/// Dropping a `JoinHandle` detaches the task: it keeps running.
+pub struct JoinHandle<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        self.receiver.poll_unpin(cx)
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Returns the task's output if the task has completed, or `None`
+    /// otherwise.
+    fn try_take(&mut self) -> Option<Result<T, Canceled>> {
+        match self.receiver.try_recv() {
+            Ok(Some(r)) => Some(Ok(r)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Creates a new task on the given thread and schedules it.
+fn spawn_task<F>(thread: Thread, fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let (s, r) = oneshot::channel::<F::Output>();
+
+    let task = Arc::new(Task {
+        thread,
+        future: UnsafeCell::new(Some(
+            async move {
+                // The receiver may be gone if the task has been detached.
+                s.send(fut.await).ok();
+            }
+            .boxed_local(),
+        )),
+        scheduled: AtomicBool::new(false),
+    });
+
+    task.schedule();
+
+    JoinHandle {
+        receiver: r,
+    }
+}
+
+impl Thread {
+    /// Spawns a future on this thread. The caller must be running on this
+    /// thread, and the future is never polled elsewhere, so it needs not be
+    /// `Send`.
+    ///
+    /// # Arguments
+    ///
+    /// * `fut`: Future to run.
+    pub fn spawn_local<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        assert_eq!(
+            Thread::current(),
+            Some(*self),
+            "spawn_local() must be called on the target thread"
+        );
+        spawn_task(*self, fut)
+    }
+
+    /// Spawns a future on this thread from any thread. The future is moved
+    /// to this thread once, and then polled only on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `fut`: Future to run.
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        spawn_task(*self, fut)
+    }
+
+    /// Runs a future to completion on this thread, polling the thread in
+    /// a loop until the future resolves. While the thread is idle, the
+    /// calling OS thread sleeps between polls, for up to a millisecond.
+    ///
+    /// This is intended to drive the primary thread
+    /// (`Thread::primary().block_on(...)`) from outside of a reactor, e.g.
+    /// during start up or shut down. It must not be called from a reactor,
+    /// or from a future or poller running on any SPDK thread: it blocks the
+    /// calling OS thread until the future resolves.
+    ///
+    /// # Arguments
+    ///
+    /// * `fut`: Future to run.
+    pub fn block_on<F>(&self, fut: F) -> F::Output
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let _g = CurrentThreadGuard::new();
+        self.set_current();
+
+        let mut h = self.spawn_local(fut);
+        let mut backoff = BLOCK_ON_MIN_BACKOFF;
+
+        loop {
+            let rc = unsafe { spdk_thread_poll(self.as_ptr(), 0, 0) };
+
+            if let Some(r) = h.try_take() {
+                return r.expect("block_on(): task has been dropped");
+            }
+
+            // Back off while the thread has nothing to do, so that waiting
+            // for e.g. a device does not spin the CPU.
+            if rc > 0 {
+                backoff = BLOCK_ON_MIN_BACKOFF;
+            } else {
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(BLOCK_ON_MAX_BACKOFF);
+            }
+        }
+    }
+}
@@ -8,9 +8,18 @@ use serde::Serialize;
 
 use crate::{
     libspdk::{
+        spdk_json_write_array_begin,
         spdk_json_write_array_end,
         spdk_json_write_ctx,
+        spdk_json_write_name,
         spdk_json_write_named_array_begin,
+        spdk_json_write_named_bool,
+        spdk_json_write_named_int64,
+        spdk_json_write_named_object_begin,
+        spdk_json_write_named_string,
+        spdk_json_write_named_uint64,
+        spdk_json_write_object_begin,
+        spdk_json_write_object_end,
         spdk_json_write_val_raw,
     },
     SpdkError,
@@ -98,6 +107,149 @@ impl JsonWriteContext {
         }
     }
 
+    /// Begins an unnamed array.
+    pub fn write_array_begin(&self) -> SpdkResult<()> {
+        Self::check(unsafe { spdk_json_write_array_begin(self.as_ptr()) })
+    }
+
+    /// Begins an unnamed object.
+    pub fn write_object_begin(&self) -> SpdkResult<()> {
+        Self::check(unsafe { spdk_json_write_object_begin(self.as_ptr()) })
+    }
+
+    /// Begins a named object, within an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: Object name.
+    pub fn write_named_object_begin(&self, name: &str) -> SpdkResult<()> {
+        let cname = CString::new(name).unwrap();
+        Self::check(unsafe {
+            spdk_json_write_named_object_begin(self.as_ptr(), cname.as_ptr())
+        })
+    }
+
+    /// Ends the current object.
+    pub fn write_object_end(&self) -> SpdkResult<()> {
+        Self::check(unsafe { spdk_json_write_object_end(self.as_ptr()) })
+    }
+
+    /// Writes a name within an object. It must be followed by a value.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: Value name.
+    pub fn write_name(&self, name: &str) -> SpdkResult<()> {
+        let cname = CString::new(name).unwrap();
+        Self::check(unsafe {
+            spdk_json_write_name(self.as_ptr(), cname.as_ptr())
+        })
+    }
+
+    /// Writes a named string value, within an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: Value name.
+    /// * `val`: String value.
+    pub fn write_named_string(&self, name: &str, val: &str) -> SpdkResult<()> {
+        let cname = CString::new(name).unwrap();
+        let cval = CString::new(val).unwrap();
+        Self::check(unsafe {
+            spdk_json_write_named_string(
+                self.as_ptr(),
+                cname.as_ptr(),
+                cval.as_ptr(),
+            )
+        })
+    }
+
+    /// Writes a named unsigned integer value, within an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: Value name.
+    /// * `val`: Integer value.
+    pub fn write_named_u64(&self, name: &str, val: u64) -> SpdkResult<()> {
+        let cname = CString::new(name).unwrap();
+        Self::check(unsafe {
+            spdk_json_write_named_uint64(self.as_ptr(), cname.as_ptr(), val)
+        })
+    }
+
+    /// Writes a named signed integer value, within an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: Value name.
+    /// * `val`: Integer value.
+    pub fn write_named_i64(&self, name: &str, val: i64) -> SpdkResult<()> {
+        let cname = CString::new(name).unwrap();
+        Self::check(unsafe {
+            spdk_json_write_named_int64(self.as_ptr(), cname.as_ptr(), val)
+        })
+    }
+
+    /// Writes a named boolean value, within an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: Value name.
+    /// * `val`: Boolean value.
+    pub fn write_named_bool(&self, name: &str, val: bool) -> SpdkResult<()> {
+        let cname = CString::new(name).unwrap();
+        Self::check(unsafe {
+            spdk_json_write_named_bool(self.as_ptr(), cname.as_ptr(), val)
+        })
+    }
+
+    /// Writes a named serializable value, within an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: Value name.
+    /// * `val`: Value to serialize.
+    pub fn write_named<T>(&self, name: &str, val: &T) -> SpdkResult<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_name(name)?;
+        self.write(val)
+    }
+
+    /// Writes an RPC call object `{"method": <method>, "params": <params>}`,
+    /// as expected in the output of `save_config` to recreate an object
+    /// when the configuration is replayed.
+    ///
+    /// # Arguments
+    ///
+    /// * `method`: RPC method name.
+    /// * `params`: RPC parameters.
+    pub fn write_rpc_call<T>(&self, method: &str, params: &T) -> SpdkResult<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_object_begin()?;
+        self.write_named_string("method", method)?;
+        self.write_named("params", params)?;
+        self.write_object_end()
+    }
+
+    /// Converts an SPDK JSON write return code into a result.
+    ///
+    /// # Arguments
+    ///
+    /// * `rc`: Return code.
+    fn check(rc: i32) -> SpdkResult<()> {
+        if rc == 0 {
+            Ok(())
+        } else {
+            Err(SpdkError::JsonWriteFailed {
+                code: rc,
+            })
+        }
+    }
+
     /// TODO
     ///
     /// # Arguments
@@ -25,71 +25,132 @@ pub mod cpu_cores;
 pub mod ffihelper;
 pub mod libspdk;
 
+mod app;
 mod bdev;
 mod bdev_async;
 mod bdev_builder;
 mod bdev_desc;
+mod bdev_desc_io;
 mod bdev_io;
+mod bdev_io_forward;
+mod bdev_io_wait;
 mod bdev_iter;
 mod bdev_module;
 mod bdev_ops;
+mod dif;
 mod dma;
 mod error;
+mod executor;
 mod io_channel;
 mod io_device_traverse;
 mod io_devices;
 mod io_type;
 mod io_vec;
 mod json_write_context;
+mod memory_domain;
 mod nvme;
+mod nvme_features;
+mod nvme_identify;
+mod nvme_log;
+mod nvme_passthru;
+mod nvme_reservation;
 mod nvmf;
 mod poller;
+mod spin_stat;
 mod thread;
 mod unsafe_types;
 mod untyped_bdev;
 mod uuid;
+mod zcopy;
+mod zone;
 
 pub use crate::{
+    app::{AppBuilder, AppError, LogLevel, SpdkApp},
     bdev::Bdev,
     bdev_async::{BdevAsyncCallContext, BdevStats},
     bdev_builder::BdevBuilder,
     bdev_desc::{BdevDesc, BdevDescError, BdevEvent, LbaRange, LbaRangeLock},
     bdev_io::BdevIo,
+    bdev_io_forward::ForwardHook,
     bdev_iter::{BdevGlobalIter, BdevModuleIter},
     bdev_module::{
         BdevModule,
         BdevModuleBuild,
         BdevModuleBuilder,
+        RegisteredModule,
+        WithModuleAsyncFini,
+        WithModuleAsyncFiniStart,
+        WithModuleAsyncInit,
+        WithModuleBdevData,
         WithModuleConfigJson,
+        WithModuleExamine,
         WithModuleFini,
+        WithModuleFiniStart,
         WithModuleGetCtxSize,
         WithModuleInit,
+        WithModuleInitComplete,
     },
-    bdev_ops::BdevOps,
+    bdev_ops::{BdevOps, WithBdevModuleCtx},
     cpu_cores::{Core, CoreIterator, Cores, RoundRobinCoreSelector},
+    dif::{dif_check_flags, DifContext, DifError, DifType},
     dma::{DmaBuf, DmaError},
     error::{spdk_error, SpdkError, SpdkResult},
+    executor::JoinHandle,
     io_channel::{IoChannel, IoChannelGuard},
     io_device_traverse::{ChannelTraverseStatus, IoDeviceChannelTraverse},
     io_devices::IoDevice,
     io_type::{IoStatus, IoType},
     io_vec::{AsIoVecPtr, AsIoVecs, IoVec},
     json_write_context::JsonWriteContext,
+    memory_domain::MemoryDomain,
     nvme::{
         nvme_admin_opc,
+        nvme_feat,
+        nvme_identify_cns,
+        nvme_log_page,
         nvme_nvm_opcode,
         nvme_reservation_acquire_action,
         nvme_reservation_register_action,
         nvme_reservation_register_cptpl,
+        nvme_reservation_release_action,
         nvme_reservation_type,
         NvmeStatus,
     },
+    nvme_features::{NvmeFeature, NvmeFeatureId, NvmeFeatureSelect},
+    nvme_identify::{
+        NvmeControllerData,
+        NvmeLbaFormat,
+        NvmeNamespaceData,
+        NvmeNsIdDescriptor,
+    },
+    nvme_log::{
+        NvmeAnaGroup,
+        NvmeAnaLog,
+        NvmeAnaState,
+        NvmeChangedNsList,
+        NvmeErrorLogEntry,
+        NvmeFwSlotLog,
+        NvmeSmartLog,
+    },
+    nvme_passthru::{NvmeCommand, NvmeCompletion},
+    nvme_reservation::{
+        Reservation,
+        ReservationAcquireAction,
+        ReservationCptpl,
+        ReservationRegisterAction,
+        ReservationRegistrant,
+        ReservationReport,
+        ReservationType,
+    },
     nvmf::{NvmfController, NvmfSubsystemEvent},
     poller::{Poller, PollerBuilder},
+    spin_stat::SpinTracker,
     thread::{CurrentThreadGuard, Thread},
     unsafe_types::{UnsafeData, UnsafeRef},
     untyped_bdev::UntypedBdev,
     uuid::Uuid,
+    zcopy::{ZcopyIo, ZcopyPhase},
+    zone::{ZoneAction, ZoneInfo, ZoneState},
 };
 
 /// TODO
@@ -0,0 +1,224 @@
+///! Memory domains and accel sequences of bdev I/Os.
+use std::{os::raw::c_void, ptr::NonNull};
+
+use futures::channel::oneshot;
+use nix::errno::Errno;
+
+use crate::{
+    ffihelper::{cb_arg, done_errno_cb, drop_cb_arg, AsStr, ErrnoResult},
+    libspdk::{
+        iovec,
+        spdk_accel_sequence,
+        spdk_memory_domain,
+        spdk_memory_domain_get_dma_device_id,
+        spdk_memory_domain_pull_data,
+        spdk_memory_domain_push_data,
+    },
+    AsIoVecPtr,
+    BdevIo,
+    BdevOps,
+    IoVec,
+};
+
+/// Wrapper for SPDK `spdk_memory_domain`: a memory domain that data buffers
+/// of an I/O may belong to, e.g. RDMA-registered memory of an NVMe-oF
+/// transport. Memory domains are owned by SPDK or by the module that
+/// created them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryDomain {
+    inner: NonNull<spdk_memory_domain>,
+}
+
+impl MemoryDomain {
+    /// Returns the identifier of the DMA device of this memory domain.
+    pub fn dma_device_id(&self) -> String {
+        unsafe {
+            spdk_memory_domain_get_dma_device_id(self.as_ptr())
+                .as_str()
+                .to_string()
+        }
+    }
+
+    /// Copies data from buffers in this memory domain into local buffers.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx`: Memory domain context of the source buffers.
+    /// * `src`: Source buffers in this memory domain.
+    /// * `dst`: Local destination buffers.
+    ///
+    /// # Safety
+    ///
+    /// `ctx` must be the memory domain context of the source buffers. Both
+    /// I/O vectors and the memory they point to must stay valid until the
+    /// transfer completes: the returned future must be polled to completion,
+    /// and must not be dropped before.
+    pub async unsafe fn pull_data(
+        &self,
+        ctx: *mut c_void,
+        src: &[IoVec],
+        dst: &mut [IoVec],
+    ) -> ErrnoResult<()> {
+        let (s, r) = oneshot::channel::<ErrnoResult<()>>();
+        let arg = cb_arg(s);
+
+        let rc = spdk_memory_domain_pull_data(
+            self.as_ptr(),
+            ctx,
+            src.as_io_vec_ptr() as *mut iovec,
+            src.len() as u32,
+            dst.as_io_vec_ptr() as *mut iovec,
+            dst.len() as u32,
+            Some(done_errno_cb),
+            arg,
+        );
+
+        Self::wait(rc, arg, r).await
+    }
+
+    /// Copies data from local buffers into buffers in this memory domain.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx`: Memory domain context of the destination buffers.
+    /// * `dst`: Destination buffers in this memory domain.
+    /// * `src`: Local source buffers.
+    ///
+    /// # Safety
+    ///
+    /// `ctx` must be the memory domain context of the destination buffers.
+    /// Both I/O vectors and the memory they point to must stay valid until
+    /// the transfer completes: the returned future must be polled to
+    /// completion, and must not be dropped before.
+    pub async unsafe fn push_data(
+        &self,
+        ctx: *mut c_void,
+        dst: &mut [IoVec],
+        src: &[IoVec],
+    ) -> ErrnoResult<()> {
+        let (s, r) = oneshot::channel::<ErrnoResult<()>>();
+        let arg = cb_arg(s);
+
+        let rc = spdk_memory_domain_push_data(
+            self.as_ptr(),
+            ctx,
+            dst.as_io_vec_ptr() as *mut iovec,
+            dst.len() as u32,
+            src.as_io_vec_ptr() as *mut iovec,
+            src.len() as u32,
+            Some(done_errno_cb),
+            arg,
+        );
+
+        Self::wait(rc, arg, r).await
+    }
+
+    /// Waits for a data transfer to complete, or drops the callback
+    /// argument if it failed to start.
+    ///
+    /// # Arguments
+    ///
+    /// * `rc`: Return code of the transfer function.
+    /// * `arg`: Callback argument made by `cb_arg`.
+    /// * `r`: Receiver of the transfer result.
+    async fn wait(
+        rc: i32,
+        arg: *mut c_void,
+        r: oneshot::Receiver<ErrnoResult<()>>,
+    ) -> ErrnoResult<()> {
+        if rc != 0 {
+            drop_cb_arg::<ErrnoResult<()>>(arg);
+            return Err(Errno::from_i32(rc.abs()));
+        }
+
+        r.await.expect("Memory domain transfer sender is gone")
+    }
+
+    /// Makes a new `MemoryDomain` from a raw SPDK pointer.
+    /// Returns `None` for a null pointer.
+    ///
+    /// # Arguments
+    ///
+    /// * `ptr`: SPDK memory domain pointer.
+    pub fn legacy_from_ptr(ptr: *mut spdk_memory_domain) -> Option<Self> {
+        NonNull::new(ptr).map(|inner| Self {
+            inner,
+        })
+    }
+
+    /// Returns a pointer to the underlying SPDK memory domain.
+    pub fn legacy_as_ptr(&self) -> *mut spdk_memory_domain {
+        self.as_ptr()
+    }
+
+    /// Returns a pointer to the underlying SPDK memory domain.
+    fn as_ptr(&self) -> *mut spdk_memory_domain {
+        self.inner.as_ptr()
+    }
+}
+
+impl<BdevData> BdevIo<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Returns the memory domain of the data buffers of this I/O, or `None`
+    /// if the buffers are local memory.
+    #[inline]
+    pub fn memory_domain(&self) -> Option<MemoryDomain> {
+        unsafe {
+            MemoryDomain::legacy_from_ptr(self.as_ref().u.bdev.memory_domain)
+        }
+    }
+
+    /// Returns the memory domain context of the data buffers of this I/O.
+    #[inline]
+    pub fn memory_domain_ctx(&self) -> *mut c_void {
+        unsafe { self.as_ref().u.bdev.memory_domain_ctx }
+    }
+
+    /// Returns the accel sequence attached to this I/O, or a null pointer.
+    /// It is only set if the Bdev supports accel sequences for this I/O type
+    /// (see `BdevOps::accel_sequence_supported`).
+    #[inline]
+    pub fn accel_sequence(&self) -> *mut spdk_accel_sequence {
+        unsafe { self.as_ref().u.bdev.accel_sequence }
+    }
+
+    /// Copies the data of this I/O into local buffers, e.g. for a write.
+    /// The I/O must have a memory domain.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst`: Local destination buffers.
+    ///
+    /// # Safety
+    ///
+    /// The I/O must not be completed, and `dst` must stay valid, until the
+    /// transfer completes: the returned future must be polled to completion,
+    /// and must not be dropped before.
+    pub async unsafe fn pull_data(&self, dst: &mut [IoVec]) -> ErrnoResult<()> {
+        let domain = self.memory_domain().ok_or(Errno::EINVAL)?;
+        domain
+            .pull_data(self.memory_domain_ctx(), self.iovs(), dst)
+            .await
+    }
+
+    /// Copies local buffers into the data buffers of this I/O, e.g. for a
+    /// read. The I/O must have a memory domain.
+    ///
+    /// # Arguments
+    ///
+    /// * `src`: Local source buffers.
+    ///
+    /// # Safety
+    ///
+    /// The I/O must not be completed, and `src` must stay valid, until the
+    /// transfer completes: the returned future must be polled to completion,
+    /// and must not be dropped before.
+    pub async unsafe fn push_data(&self, src: &[IoVec]) -> ErrnoResult<()> {
+        let domain = self.memory_domain().ok_or(Errno::EINVAL)?;
+        domain
+            .push_data(self.memory_domain_ctx(), self.iovs_mut(), src)
+            .await
+    }
+}
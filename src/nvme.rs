@@ -1,28 +1,52 @@
 use nix::errno::Errno;
 use std::{
+    convert::TryInto,
     fmt::{Debug, Formatter},
     mem::transmute,
 };
 
-use crate::libspdk::{
-    spdk_bdev_io,
-    spdk_bdev_io_get_nvme_status,
-    spdk_nvme_command_specific_status_code,
-    spdk_nvme_cpl,
-    spdk_nvme_generic_command_status_code,
-    spdk_nvme_media_error_status_code,
-    spdk_nvme_path_status_code,
-    spdk_nvme_status,
-    spdk_nvme_status_code_type,
-    spdk_nvmf_request,
-    SPDK_NVME_SCT_COMMAND_SPECIFIC,
-    SPDK_NVME_SCT_GENERIC,
-    SPDK_NVME_SCT_MEDIA_ERROR,
-    SPDK_NVME_SCT_PATH,
-    SPDK_NVME_SCT_VENDOR_SPECIFIC,
-    SPDK_NVME_SC_DATA_TRANSFER_ERROR,
-    SPDK_NVME_SC_DEALLOCATED_OR_UNWRITTEN_BLOCK,
-    SPDK_NVME_SC_SUCCESS,
+use crate::{
+    libspdk::{
+        spdk_bdev_io,
+        spdk_bdev_io_get_nvme_status,
+        spdk_nvme_command_specific_status_code,
+        spdk_nvme_cpl,
+        spdk_nvme_generic_command_status_code,
+        spdk_nvme_media_error_status_code,
+        spdk_nvme_path_status_code,
+        spdk_nvme_status,
+        spdk_nvme_status_code_type,
+        spdk_nvmf_request,
+        SPDK_NVME_SCT_COMMAND_SPECIFIC,
+        SPDK_NVME_SCT_GENERIC,
+        SPDK_NVME_SCT_MEDIA_ERROR,
+        SPDK_NVME_SCT_PATH,
+        SPDK_NVME_SCT_VENDOR_SPECIFIC,
+        SPDK_NVME_SC_ABORTED_BY_HOST,
+        SPDK_NVME_SC_ABORTED_BY_REQUEST,
+        SPDK_NVME_SC_ABORTED_FAILED_FUSED,
+        SPDK_NVME_SC_ABORTED_MISSING_FUSED,
+        SPDK_NVME_SC_ABORTED_POWER_LOSS,
+        SPDK_NVME_SC_ABORTED_PREEMPT,
+        SPDK_NVME_SC_ABORTED_SQ_DELETION,
+        SPDK_NVME_SC_ACCESS_DENIED,
+        SPDK_NVME_SC_CAPACITY_EXCEEDED,
+        SPDK_NVME_SC_COMPARE_FAILURE,
+        SPDK_NVME_SC_DATA_TRANSFER_ERROR,
+        SPDK_NVME_SC_DEALLOCATED_OR_UNWRITTEN_BLOCK,
+        SPDK_NVME_SC_INTERNAL_DEVICE_ERROR,
+        SPDK_NVME_SC_INTERNAL_PATH_ERROR,
+        SPDK_NVME_SC_INVALID_FIELD,
+        SPDK_NVME_SC_INVALID_NAMESPACE_OR_FORMAT,
+        SPDK_NVME_SC_INVALID_OPCODE,
+        SPDK_NVME_SC_LBA_OUT_OF_RANGE,
+        SPDK_NVME_SC_NAMESPACE_IS_WRITE_PROTECTED,
+        SPDK_NVME_SC_NAMESPACE_NOT_READY,
+        SPDK_NVME_SC_RESERVATION_CONFLICT,
+        SPDK_NVME_SC_SUCCESS,
+        SPDK_NVME_SC_UNRECOVERED_READ_ERROR,
+    },
+    IoStatus,
 };
 
 /// Accessors for `spdk_nvme_cpl` (completion queue entry) struct.
@@ -60,6 +84,12 @@ impl spdk_nvme_status {
     pub fn status(&self) -> NvmeStatus {
         NvmeStatus::from(*self)
     }
+
+    /// Determines if the command may succeed if retried, according to
+    /// its status and Do Not Retry bit.
+    pub fn is_retryable(&self) -> bool {
+        self.status().is_retryable(self.dnr() != 0)
+    }
 }
 
 /// Status code types.
@@ -97,6 +127,9 @@ impl NvmeStatus {
     pub const UNWRITTEN_BLOCK: Self =
         Self::Media(SPDK_NVME_SC_DEALLOCATED_OR_UNWRITTEN_BLOCK);
 
+    /// Shorthand for SPDK_NVME_SC_COMPARE_FAILURE.
+    pub const COMPARE_FAILURE: Self = Self::Media(SPDK_NVME_SC_COMPARE_FAILURE);
+
     /// Shorthand for a vendor-specific ENOSPC error.
     pub const NO_SPACE: Self = Self::VendorSpecific(Errno::ENOSPC as i32);
 
@@ -137,6 +170,177 @@ impl NvmeStatus {
     pub fn is_no_space(&self) -> bool {
         *self == Self::NO_SPACE
     }
+
+    /// Determines if a command that failed with this status may succeed if
+    /// retried.
+    /// Path errors are retryable regardless of the DNR bit, as the command
+    /// may succeed on another path.
+    ///
+    /// # Arguments
+    ///
+    /// * `dnr`: Do Not Retry bit of the completion.
+    pub fn is_retryable(&self, dnr: bool) -> bool {
+        if self.is_success() || self.is_reservation_conflict() {
+            return false;
+        }
+
+        self.is_path_error() || !dnr
+    }
+
+    /// Determines if this status is a path related error.
+    #[inline(always)]
+    pub fn is_path_error(&self) -> bool {
+        matches!(self, Self::Path(_))
+    }
+
+    /// Determines if this status is a reservation conflict.
+    #[inline(always)]
+    pub fn is_reservation_conflict(&self) -> bool {
+        *self == Self::Generic(SPDK_NVME_SC_RESERVATION_CONFLICT)
+    }
+
+    /// Determines if this status is a media or data integrity error.
+    #[inline(always)]
+    pub fn is_media_error(&self) -> bool {
+        matches!(self, Self::Media(_))
+    }
+
+    /// Determines if this status means that the command has been aborted.
+    pub fn is_aborted(&self) -> bool {
+        matches!(
+            self,
+            Self::Generic(
+                SPDK_NVME_SC_ABORTED_POWER_LOSS
+                    | SPDK_NVME_SC_ABORTED_BY_REQUEST
+                    | SPDK_NVME_SC_ABORTED_SQ_DELETION
+                    | SPDK_NVME_SC_ABORTED_FAILED_FUSED
+                    | SPDK_NVME_SC_ABORTED_MISSING_FUSED
+                    | SPDK_NVME_SC_ABORTED_PREEMPT
+            ) | Self::Path(SPDK_NVME_SC_ABORTED_BY_HOST)
+        )
+    }
+}
+
+/// Errno values that convert to and from NVMe statuses one-to-one.
+const ERRNO_NVME_STATUS: [(Errno, NvmeStatus); 14] = [
+    (
+        Errno::EINVAL,
+        NvmeStatus::Generic(SPDK_NVME_SC_INVALID_FIELD),
+    ),
+    (
+        Errno::EOPNOTSUPP,
+        NvmeStatus::Generic(SPDK_NVME_SC_INVALID_OPCODE),
+    ),
+    (
+        Errno::EIO,
+        NvmeStatus::Generic(SPDK_NVME_SC_INTERNAL_DEVICE_ERROR),
+    ),
+    (
+        Errno::ECANCELED,
+        NvmeStatus::Generic(SPDK_NVME_SC_ABORTED_BY_REQUEST),
+    ),
+    (
+        Errno::ERANGE,
+        NvmeStatus::Generic(SPDK_NVME_SC_LBA_OUT_OF_RANGE),
+    ),
+    (
+        Errno::ENXIO,
+        NvmeStatus::Generic(SPDK_NVME_SC_INVALID_NAMESPACE_OR_FORMAT),
+    ),
+    (
+        Errno::EAGAIN,
+        NvmeStatus::Generic(SPDK_NVME_SC_NAMESPACE_NOT_READY),
+    ),
+    (
+        Errno::EBADE,
+        NvmeStatus::Generic(SPDK_NVME_SC_RESERVATION_CONFLICT),
+    ),
+    (
+        Errno::EROFS,
+        NvmeStatus::Generic(SPDK_NVME_SC_NAMESPACE_IS_WRITE_PROTECTED),
+    ),
+    (Errno::EPROTO, NvmeStatus::DATA_TRANSFER_ERROR),
+    (Errno::EILSEQ, NvmeStatus::COMPARE_FAILURE),
+    (
+        Errno::ENODATA,
+        NvmeStatus::Media(SPDK_NVME_SC_UNRECOVERED_READ_ERROR),
+    ),
+    (Errno::EACCES, NvmeStatus::Media(SPDK_NVME_SC_ACCESS_DENIED)),
+    (
+        Errno::ENOLINK,
+        NvmeStatus::Path(SPDK_NVME_SC_INTERNAL_PATH_ERROR),
+    ),
+];
+
+/// Converts an errno into `NvmeStatus`.
+/// Errno values without an NVMe equivalent are converted into
+/// vendor-specific statuses, so that the conversion back to `Errno` always
+/// gives the original value. `Errno::UnknownErrno` (zero) means success.
+impl From<Errno> for NvmeStatus {
+    fn from(e: Errno) -> Self {
+        if e == Errno::UnknownErrno {
+            return Self::SUCCESS;
+        }
+
+        ERRNO_NVME_STATUS
+            .iter()
+            .find(|(en, _)| *en == e)
+            .map_or(Self::VendorSpecific(e as i32), |(_, s)| *s)
+    }
+}
+
+/// Converts `NvmeStatus` into the closest errno value.
+/// Success converts into `Errno::UnknownErrno` (zero).
+impl From<NvmeStatus> for Errno {
+    fn from(s: NvmeStatus) -> Self {
+        if s.is_success() {
+            return Errno::UnknownErrno;
+        }
+
+        if let Some((e, _)) = ERRNO_NVME_STATUS.iter().find(|(_, st)| *st == s)
+        {
+            return *e;
+        }
+
+        match s {
+            NvmeStatus::VendorSpecific(e) if e > 0 => Errno::from_i32(e),
+            NvmeStatus::Generic(SPDK_NVME_SC_CAPACITY_EXCEEDED) => {
+                Errno::ENOSPC
+            }
+            s if s.is_aborted() => Errno::ECANCELED,
+            NvmeStatus::Path(_) => Errno::ENOLINK,
+            NvmeStatus::Media(_) => Errno::ENODATA,
+            _ => Errno::EIO,
+        }
+    }
+}
+
+/// Converts `NvmeStatus` into a bdev I/O status.
+impl From<NvmeStatus> for IoStatus {
+    fn from(s: NvmeStatus) -> Self {
+        if s.is_success() {
+            IoStatus::Success
+        } else if s == NvmeStatus::COMPARE_FAILURE {
+            IoStatus::MisCompared
+        } else if s.is_aborted() {
+            IoStatus::Aborted
+        } else {
+            IoStatus::NvmeError
+        }
+    }
+}
+
+/// Converts a bdev I/O status into `NvmeStatus`, in the same way as
+/// `spdk_bdev_io_get_nvme_status` does for non-NVMe completions.
+impl From<IoStatus> for NvmeStatus {
+    fn from(s: IoStatus) -> Self {
+        match s {
+            IoStatus::Success => Self::SUCCESS,
+            IoStatus::MisCompared => Self::COMPARE_FAILURE,
+            IoStatus::Aborted => Self::Generic(SPDK_NVME_SC_ABORTED_BY_REQUEST),
+            _ => Self::Generic(SPDK_NVME_SC_INTERNAL_DEVICE_ERROR),
+        }
+    }
 }
 
 impl Debug for NvmeStatus {
@@ -210,17 +414,73 @@ impl From<*const spdk_nvme_cpl> for NvmeStatus {
     }
 }
 
+/// Reads a little-endian `u16` at the given offset.
+pub(crate) fn le16(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(b[off .. off + 2].try_into().unwrap())
+}
+
+/// Reads a little-endian `u32` at the given offset.
+pub(crate) fn le32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off .. off + 4].try_into().unwrap())
+}
+
+/// Reads a little-endian `u64` at the given offset.
+pub(crate) fn le64(b: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(b[off .. off + 8].try_into().unwrap())
+}
+
+/// Reads a little-endian `u128` at the given offset.
+pub(crate) fn le128(b: &[u8], off: usize) -> u128 {
+    u128::from_le_bytes(b[off .. off + 16].try_into().unwrap())
+}
+
+/// Decodes a space- or NUL-padded ASCII string field.
+pub(crate) fn ascii(b: &[u8]) -> String {
+    String::from_utf8_lossy(b)
+        .trim_end_matches(|c| c == ' ' || c == '\0')
+        .to_string()
+}
+
 /// NVMe Admin opcode, from nvme_spec.h
 pub mod nvme_admin_opc {
-    // pub const GET_LOG_PAGE: u8 = 0x02;
+    pub const GET_LOG_PAGE: u8 = 0x02;
     pub const IDENTIFY: u8 = 0x06;
     // pub const ABORT: u8 = 0x08;
-    // pub const SET_FEATURES: u8 = 0x09;
-    // pub const GET_FEATURES: u8 = 0x0a;
+    pub const SET_FEATURES: u8 = 0x09;
+    pub const GET_FEATURES: u8 = 0x0a;
     // Vendor-specific
     pub const CREATE_SNAPSHOT: u8 = 0xc1;
 }
 
+/// Feature identifiers, from nvme_spec.h
+pub mod nvme_feat {
+    pub const ARBITRATION: u8 = 0x01;
+    pub const POWER_MANAGEMENT: u8 = 0x02;
+    pub const TEMPERATURE_THRESHOLD: u8 = 0x04;
+    pub const VOLATILE_WRITE_CACHE: u8 = 0x06;
+    pub const NUMBER_OF_QUEUES: u8 = 0x07;
+    pub const ASYNC_EVENT_CONFIGURATION: u8 = 0x0b;
+    pub const KEEP_ALIVE_TIMER: u8 = 0x0f;
+    pub const HOST_IDENTIFIER: u8 = 0x81;
+}
+
+/// Identify Controller or Namespace Structure (CNS) values, from nvme_spec.h
+pub mod nvme_identify_cns {
+    pub const NS: u8 = 0x00;
+    pub const CTRLR: u8 = 0x01;
+    pub const ACTIVE_NS_LIST: u8 = 0x02;
+    pub const NS_ID_DESCRIPTOR_LIST: u8 = 0x03;
+}
+
+/// Log page identifiers, from nvme_spec.h
+pub mod nvme_log_page {
+    pub const ERROR: u8 = 0x01;
+    pub const HEALTH_INFORMATION: u8 = 0x02;
+    pub const FIRMWARE_SLOT: u8 = 0x03;
+    pub const CHANGED_NS_LIST: u8 = 0x04;
+    pub const ASYMMETRIC_NAMESPACE_ACCESS: u8 = 0x0c;
+}
+
 /// NVM command set opcodes, from nvme_spec.h
 pub mod nvme_nvm_opcode {
     // pub const FLUSH: u8 = 0x00;
@@ -266,3 +526,90 @@ pub mod nvme_reservation_acquire_action {
     pub const PREEMPT: u8 = 0x1;
     pub const PREEMPT_ABORT: u8 = 0x2;
 }
+
+/// Reservation release actions, from nvme_spec.h
+pub mod nvme_reservation_release_action {
+    pub const RELEASE: u8 = 0x0;
+    pub const CLEAR: u8 = 0x1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn success_round_trip() {
+        assert_eq!(NvmeStatus::from(Errno::UnknownErrno), NvmeStatus::SUCCESS);
+        assert_eq!(Errno::from(NvmeStatus::SUCCESS), Errno::UnknownErrno);
+        assert_eq!(IoStatus::from(NvmeStatus::SUCCESS), IoStatus::Success);
+        assert_eq!(NvmeStatus::from(IoStatus::Success), NvmeStatus::SUCCESS);
+    }
+
+    #[test]
+    fn errno_round_trip() {
+        for (e, s) in ERRNO_NVME_STATUS.iter() {
+            assert_eq!(NvmeStatus::from(*e), *s, "{e}");
+            assert_eq!(Errno::from(*s), *e, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn errno_vendor_specific_round_trip() {
+        for e in [Errno::ENOSPC, Errno::ENOMEM, Errno::EEXIST, Errno::ENODEV] {
+            let s = NvmeStatus::from(e);
+            assert_eq!(s, NvmeStatus::VendorSpecific(e as i32));
+            assert_eq!(Errno::from(s), e);
+        }
+
+        assert!(NvmeStatus::from(Errno::ENOSPC).is_no_space());
+    }
+
+    #[test]
+    fn nvme_status_to_errno() {
+        assert_eq!(
+            Errno::from(NvmeStatus::Generic(SPDK_NVME_SC_CAPACITY_EXCEEDED)),
+            Errno::ENOSPC
+        );
+        assert_eq!(
+            Errno::from(NvmeStatus::Generic(SPDK_NVME_SC_ABORTED_SQ_DELETION)),
+            Errno::ECANCELED
+        );
+        assert_eq!(
+            Errno::from(NvmeStatus::Path(SPDK_NVME_SC_ABORTED_BY_HOST)),
+            Errno::ECANCELED
+        );
+        assert_eq!(Errno::from(NvmeStatus::UNWRITTEN_BLOCK), Errno::ENODATA);
+        assert_eq!(Errno::from(NvmeStatus::Unknown(5, 1)), Errno::EIO);
+        assert_eq!(Errno::from(NvmeStatus::VendorSpecific(0)), Errno::EIO);
+    }
+
+    #[test]
+    fn io_status_round_trip() {
+        for s in [
+            IoStatus::Success,
+            IoStatus::MisCompared,
+            IoStatus::Aborted,
+            IoStatus::NvmeError,
+        ] {
+            assert_eq!(IoStatus::from(NvmeStatus::from(s)), s);
+        }
+
+        assert_eq!(
+            NvmeStatus::from(IoStatus::Failed),
+            NvmeStatus::Generic(SPDK_NVME_SC_INTERNAL_DEVICE_ERROR)
+        );
+    }
+
+    #[test]
+    fn sct_sc_round_trip() {
+        for s in [
+            NvmeStatus::SUCCESS,
+            NvmeStatus::COMPARE_FAILURE,
+            NvmeStatus::DATA_TRANSFER_ERROR,
+            NvmeStatus::Path(SPDK_NVME_SC_INTERNAL_PATH_ERROR),
+            NvmeStatus::VendorSpecific(Errno::ENOSPC as i32),
+        ] {
+            assert_eq!(NvmeStatus::from(s.as_sct_sc_codes()), s);
+        }
+    }
+}
@@ -0,0 +1,496 @@
+///! NVMe Get Features / Set Features commands.
+use crate::{
+    nvme_admin_opc,
+    nvme_feat,
+    BdevDesc,
+    BdevDescError,
+    BdevOps,
+    IoChannel,
+    NvmeCommand,
+};
+
+/// Size of the regular and the extended host identifiers.
+const HOST_ID_SIZE: usize = 8;
+const HOST_ID_EXT_SIZE: usize = 16;
+
+/// Identifier of a feature to get.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NvmeFeatureId {
+    Arbitration,
+    PowerManagement,
+    /// Temperature threshold of a sensor: 0 is the composite temperature,
+    /// 1 to 8 are temperature sensors.
+    TemperatureThreshold {
+        sensor: u8,
+        under: bool,
+    },
+    VolatileWriteCache,
+    NumberOfQueues,
+    AsyncEventConfig,
+    KeepAliveTimer,
+    /// Host identifier: 128-bit if extended, 64-bit otherwise.
+    HostIdentifier {
+        extended: bool,
+    },
+}
+
+impl NvmeFeatureId {
+    /// Returns the raw feature identifier.
+    pub fn fid(&self) -> u8 {
+        match self {
+            Self::Arbitration => nvme_feat::ARBITRATION,
+            Self::PowerManagement => nvme_feat::POWER_MANAGEMENT,
+            Self::TemperatureThreshold {
+                ..
+            } => nvme_feat::TEMPERATURE_THRESHOLD,
+            Self::VolatileWriteCache => nvme_feat::VOLATILE_WRITE_CACHE,
+            Self::NumberOfQueues => nvme_feat::NUMBER_OF_QUEUES,
+            Self::AsyncEventConfig => nvme_feat::ASYNC_EVENT_CONFIGURATION,
+            Self::KeepAliveTimer => nvme_feat::KEEP_ALIVE_TIMER,
+            Self::HostIdentifier {
+                ..
+            } => nvme_feat::HOST_IDENTIFIER,
+        }
+    }
+
+    /// Returns command dword 10 for Get Features.
+    ///
+    /// # Arguments
+    ///
+    /// * `sel`: Which value of the feature to get.
+    fn get_cdw10(&self, sel: NvmeFeatureSelect) -> u32 {
+        self.fid() as u32 | u32::from(sel) << 8
+    }
+
+    /// Returns command dword 11 for Get Features.
+    fn get_cdw11(&self) -> u32 {
+        match *self {
+            Self::TemperatureThreshold {
+                sensor,
+                under,
+            } => temp_threshold_cdw11(0, sensor, under),
+            Self::HostIdentifier {
+                extended,
+            } => extended as u32,
+            _ => 0,
+        }
+    }
+}
+
+/// Which value of a feature to get.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NvmeFeatureSelect {
+    Current,
+    Default,
+    Saved,
+}
+
+impl From<NvmeFeatureSelect> for u32 {
+    fn from(s: NvmeFeatureSelect) -> Self {
+        match s {
+            NvmeFeatureSelect::Current => 0,
+            NvmeFeatureSelect::Default => 1,
+            NvmeFeatureSelect::Saved => 2,
+        }
+    }
+}
+
+/// Decoded feature value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvmeFeature {
+    /// Command arbitration.
+    Arbitration {
+        /// Arbitration burst, as a power of two; 7 means no limit.
+        burst: u8,
+        low_weight: u8,
+        medium_weight: u8,
+        high_weight: u8,
+    },
+    /// Power management.
+    PowerManagement {
+        /// Power state.
+        power_state: u8,
+        /// Workload hint.
+        workload_hint: u8,
+    },
+    /// Temperature threshold, in Kelvin.
+    TemperatureThreshold {
+        threshold: u16,
+        /// Sensor: 0 is the composite temperature, 1 to 8 are temperature
+        /// sensors.
+        sensor: u8,
+        /// True for the under temperature threshold.
+        under: bool,
+    },
+    /// Volatile write cache enable.
+    VolatileWriteCache(bool),
+    /// Number of I/O queues. When set, these are the requested numbers;
+    /// when returned, the allocated numbers.
+    NumberOfQueues { num_sq: u32, num_cq: u32 },
+    /// Asynchronous events that are reported.
+    AsyncEventConfig(u32),
+    /// Keep alive timeout, in milliseconds.
+    KeepAliveTimer(u32),
+    /// Host identifier: 8 or 16 bytes.
+    HostIdentifier(Vec<u8>),
+}
+
+impl NvmeFeature {
+    /// Returns the raw feature identifier.
+    pub fn fid(&self) -> u8 {
+        match self {
+            Self::Arbitration {
+                ..
+            } => nvme_feat::ARBITRATION,
+            Self::PowerManagement {
+                ..
+            } => nvme_feat::POWER_MANAGEMENT,
+            Self::TemperatureThreshold {
+                ..
+            } => nvme_feat::TEMPERATURE_THRESHOLD,
+            Self::VolatileWriteCache(_) => nvme_feat::VOLATILE_WRITE_CACHE,
+            Self::NumberOfQueues {
+                ..
+            } => nvme_feat::NUMBER_OF_QUEUES,
+            Self::AsyncEventConfig(_) => nvme_feat::ASYNC_EVENT_CONFIGURATION,
+            Self::KeepAliveTimer(_) => nvme_feat::KEEP_ALIVE_TIMER,
+            Self::HostIdentifier(_) => nvme_feat::HOST_IDENTIFIER,
+        }
+    }
+
+    /// Returns command dword 10 for Set Features.
+    ///
+    /// # Arguments
+    ///
+    /// * `save`: Save the value across power cycles.
+    fn set_cdw10(&self, save: bool) -> u32 {
+        self.fid() as u32 | (save as u32) << 31
+    }
+
+    /// Returns command dword 11 for Set Features.
+    fn set_cdw11(&self) -> u32 {
+        match self {
+            Self::Arbitration {
+                burst,
+                low_weight,
+                medium_weight,
+                high_weight,
+            } => {
+                (*burst as u32 & 0x7)
+                    | (*low_weight as u32) << 8
+                    | (*medium_weight as u32) << 16
+                    | (*high_weight as u32) << 24
+            }
+            Self::PowerManagement {
+                power_state,
+                workload_hint,
+            } => {
+                (*power_state as u32 & 0x1f)
+                    | (*workload_hint as u32 & 0x7) << 5
+            }
+            Self::TemperatureThreshold {
+                threshold,
+                sensor,
+                under,
+            } => temp_threshold_cdw11(*threshold, *sensor, *under),
+            Self::VolatileWriteCache(enable) => *enable as u32,
+            Self::NumberOfQueues {
+                num_sq,
+                num_cq,
+            } => {
+                // Zero-based values.
+                (num_sq.saturating_sub(1) & 0xffff)
+                    | (num_cq.saturating_sub(1) & 0xffff) << 16
+            }
+            Self::AsyncEventConfig(v) => *v,
+            Self::KeepAliveTimer(ms) => *ms,
+            Self::HostIdentifier(id) => (id.len() == HOST_ID_EXT_SIZE) as u32,
+        }
+    }
+
+    /// Decodes a feature value returned by Get Features.
+    ///
+    /// # Arguments
+    ///
+    /// * `id`: Identifier of the feature.
+    /// * `cdw0`: Dword 0 of the completion.
+    /// * `data`: Data returned by the command, if any.
+    fn decode(id: NvmeFeatureId, cdw0: u32, data: &[u8]) -> Self {
+        match id {
+            NvmeFeatureId::Arbitration => Self::Arbitration {
+                burst: (cdw0 & 0x7) as u8,
+                low_weight: (cdw0 >> 8) as u8,
+                medium_weight: (cdw0 >> 16) as u8,
+                high_weight: (cdw0 >> 24) as u8,
+            },
+            NvmeFeatureId::PowerManagement => Self::PowerManagement {
+                power_state: (cdw0 & 0x1f) as u8,
+                workload_hint: ((cdw0 >> 5) & 0x7) as u8,
+            },
+            NvmeFeatureId::TemperatureThreshold {
+                sensor,
+                under,
+            } => Self::TemperatureThreshold {
+                threshold: cdw0 as u16,
+                sensor,
+                under,
+            },
+            NvmeFeatureId::VolatileWriteCache => {
+                Self::VolatileWriteCache(cdw0 & 1 != 0)
+            }
+            NvmeFeatureId::NumberOfQueues => Self::decode_num_queues(cdw0),
+            NvmeFeatureId::AsyncEventConfig => Self::AsyncEventConfig(cdw0),
+            NvmeFeatureId::KeepAliveTimer => Self::KeepAliveTimer(cdw0),
+            NvmeFeatureId::HostIdentifier {
+                ..
+            } => Self::HostIdentifier(data.to_vec()),
+        }
+    }
+
+    /// Decodes the numbers of allocated queues.
+    ///
+    /// # Arguments
+    ///
+    /// * `cdw0`: Dword 0 of the completion.
+    fn decode_num_queues(cdw0: u32) -> Self {
+        Self::NumberOfQueues {
+            num_sq: (cdw0 & 0xffff) + 1,
+            num_cq: (cdw0 >> 16) + 1,
+        }
+    }
+}
+
+/// Makes command dword 11 for the temperature threshold feature.
+fn temp_threshold_cdw11(threshold: u16, sensor: u8, under: bool) -> u32 {
+    threshold as u32 | (sensor as u32 & 0xf) << 16 | (under as u32) << 20
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Gets a feature of the underlying NVMe controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `id`: Feature to get.
+    /// * `sel`: Which value of the feature to get.
+    pub async fn get_feature(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        id: NvmeFeatureId,
+        sel: NvmeFeatureSelect,
+    ) -> Result<NvmeFeature, BdevDescError> {
+        let cmd = NvmeCommand::new(nvme_admin_opc::GET_FEATURES)
+            .with_cdw10(id.get_cdw10(sel))
+            .with_cdw11(id.get_cdw11());
+
+        if let NvmeFeatureId::HostIdentifier {
+            extended,
+        } = id
+        {
+            let size = if extended {
+                HOST_ID_EXT_SIZE
+            } else {
+                HOST_ID_SIZE
+            };
+            let buf = self.alloc_io_buf(size as u64)?;
+            let (cpl, cmd) =
+                self.exec_nvme_admin(chan, cmd.with_buf(buf), true).await?;
+            let buf = cmd.into_data_buf();
+            return Ok(NvmeFeature::decode(id, cpl.cdw0(), buf.as_slice()));
+        }
+
+        let (cpl, _) = self.exec_nvme_admin(chan, cmd, true).await?;
+        Ok(NvmeFeature::decode(id, cpl.cdw0(), &[]))
+    }
+
+    /// Sets a feature of the underlying NVMe controller.
+    /// For `NvmeFeature::NumberOfQueues`, returns the numbers of allocated
+    /// queues; otherwise, returns the given feature.
+    /// A host identifier must be 8 or 16 bytes long.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `feature`: Feature value to set.
+    /// * `save`: Save the value across power cycles.
+    pub async fn set_feature(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        feature: NvmeFeature,
+        save: bool,
+    ) -> Result<NvmeFeature, BdevDescError> {
+        if let NvmeFeature::HostIdentifier(id) = &feature {
+            if id.len() != HOST_ID_SIZE && id.len() != HOST_ID_EXT_SIZE {
+                return Err(BdevDescError::InvalidParams {
+                    msg: format!(
+                        "host identifier must be {} or {} bytes, got {}",
+                        HOST_ID_SIZE,
+                        HOST_ID_EXT_SIZE,
+                        id.len()
+                    ),
+                    bdev_name: self.bdev().name().to_owned(),
+                });
+            }
+        }
+
+        let cmd = NvmeCommand::new(nvme_admin_opc::SET_FEATURES)
+            .with_cdw10(feature.set_cdw10(save))
+            .with_cdw11(feature.set_cdw11());
+
+        let cmd = match &feature {
+            NvmeFeature::HostIdentifier(id) => {
+                let mut buf = self.alloc_io_buf(id.len() as u64)?;
+                buf.as_mut_slice().copy_from_slice(id);
+                cmd.with_buf(buf)
+            }
+            _ => cmd,
+        };
+
+        let (cpl, _) = self.exec_nvme_admin(chan, cmd, false).await?;
+
+        match feature {
+            NvmeFeature::NumberOfQueues {
+                ..
+            } => Ok(NvmeFeature::decode_num_queues(cpl.cdw0())),
+            f => Ok(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_cdw10() {
+        assert_eq!(
+            NvmeFeatureId::VolatileWriteCache
+                .get_cdw10(NvmeFeatureSelect::Current),
+            nvme_feat::VOLATILE_WRITE_CACHE as u32
+        );
+        assert_eq!(
+            NvmeFeatureId::NumberOfQueues.get_cdw10(NvmeFeatureSelect::Saved),
+            nvme_feat::NUMBER_OF_QUEUES as u32 | 2 << 8
+        );
+    }
+
+    #[test]
+    fn get_cdw11() {
+        let id = NvmeFeatureId::TemperatureThreshold {
+            sensor: 3,
+            under: true,
+        };
+        assert_eq!(id.get_cdw11(), 3 << 16 | 1 << 20);
+
+        let id = NvmeFeatureId::HostIdentifier {
+            extended: true,
+        };
+        assert_eq!(id.get_cdw11(), 1);
+        assert_eq!(NvmeFeatureId::KeepAliveTimer.get_cdw11(), 0);
+    }
+
+    #[test]
+    fn set_cdw10() {
+        let f = NvmeFeature::KeepAliveTimer(10000);
+        assert_eq!(f.set_cdw10(false), nvme_feat::KEEP_ALIVE_TIMER as u32);
+        assert_eq!(
+            f.set_cdw10(true),
+            nvme_feat::KEEP_ALIVE_TIMER as u32 | 1 << 31
+        );
+    }
+
+    /// Checks that a feature decodes back from its Set Features dword 11.
+    fn check_round_trip(id: NvmeFeatureId, f: NvmeFeature) {
+        assert_eq!(id.fid(), f.fid());
+        assert_eq!(NvmeFeature::decode(id, f.set_cdw11(), &[]), f);
+    }
+
+    #[test]
+    fn set_cdw11_round_trip() {
+        check_round_trip(
+            NvmeFeatureId::Arbitration,
+            NvmeFeature::Arbitration {
+                burst: 7,
+                low_weight: 1,
+                medium_weight: 2,
+                high_weight: 3,
+            },
+        );
+        check_round_trip(
+            NvmeFeatureId::PowerManagement,
+            NvmeFeature::PowerManagement {
+                power_state: 0x1f,
+                workload_hint: 0x5,
+            },
+        );
+        check_round_trip(
+            NvmeFeatureId::TemperatureThreshold {
+                sensor: 2,
+                under: false,
+            },
+            NvmeFeature::TemperatureThreshold {
+                threshold: 343,
+                sensor: 2,
+                under: false,
+            },
+        );
+        check_round_trip(
+            NvmeFeatureId::VolatileWriteCache,
+            NvmeFeature::VolatileWriteCache(true),
+        );
+        check_round_trip(
+            NvmeFeatureId::NumberOfQueues,
+            NvmeFeature::NumberOfQueues {
+                num_sq: 16,
+                num_cq: 8,
+            },
+        );
+        check_round_trip(
+            NvmeFeatureId::KeepAliveTimer,
+            NvmeFeature::KeepAliveTimer(10000),
+        );
+    }
+
+    #[test]
+    fn set_cdw11_host_identifier() {
+        assert_eq!(
+            NvmeFeature::HostIdentifier(vec![0; HOST_ID_SIZE]).set_cdw11(),
+            0
+        );
+        assert_eq!(
+            NvmeFeature::HostIdentifier(vec![0; HOST_ID_EXT_SIZE]).set_cdw11(),
+            1
+        );
+    }
+
+    #[test]
+    fn decode_num_queues() {
+        assert_eq!(
+            NvmeFeature::decode_num_queues(0),
+            NvmeFeature::NumberOfQueues {
+                num_sq: 1,
+                num_cq: 1,
+            }
+        );
+        assert_eq!(
+            NvmeFeature::decode_num_queues(0xffff_fffe),
+            NvmeFeature::NumberOfQueues {
+                num_sq: 0xffff,
+                num_cq: 0x10000,
+            }
+        );
+    }
+
+    #[test]
+    fn set_num_queues_zero() {
+        // Zero queues are requested as one.
+        let f = NvmeFeature::NumberOfQueues {
+            num_sq: 0,
+            num_cq: 0,
+        };
+        assert_eq!(f.set_cdw11(), 0);
+    }
+}
@@ -0,0 +1,458 @@
+///! NVMe Identify command and decoded Identify data structures.
+use std::{convert::TryInto, mem::size_of, slice::from_raw_parts};
+
+use crate::{
+    libspdk::{spdk_nvme_ctrlr_data, spdk_nvme_ns_data},
+    nvme::{ascii, le128, le16, le32},
+    nvme_admin_opc,
+    nvme_identify_cns,
+    BdevDesc,
+    BdevDescError,
+    BdevOps,
+    DmaBuf,
+    IoChannel,
+    NvmeCommand,
+};
+
+/// Size of an Identify data structure.
+const IDENTIFY_DATA_SIZE: u64 = 4096;
+
+/// Identify namespace identification descriptor types (NIDT).
+const NIDT_EUI64: u8 = 0x1;
+const NIDT_NGUID: u8 = 0x2;
+const NIDT_UUID: u8 = 0x3;
+const NIDT_CSI: u8 = 0x4;
+
+/// Decoded Identify Controller data structure (CNS 0x01).
+#[derive(Debug, Clone)]
+pub struct NvmeControllerData {
+    /// PCI vendor ID.
+    pub vid: u16,
+    /// PCI subsystem vendor ID.
+    pub ssvid: u16,
+    /// Serial number.
+    pub serial_number: String,
+    /// Model number.
+    pub model_number: String,
+    /// Firmware revision.
+    pub firmware_revision: String,
+    /// IEEE OUI identifier.
+    pub ieee_oui: [u8; 3],
+    /// Controller multi-path I/O and namespace sharing capabilities.
+    pub cmic: u8,
+    /// Maximum data transfer size, as a power of two of the minimum memory
+    /// page size. Zero means no limit.
+    pub mdts: u8,
+    /// Controller ID.
+    pub cntlid: u16,
+    /// NVMe specification version.
+    pub version: u32,
+    /// Optional admin command support.
+    pub oacs: u16,
+    /// Total NVM capacity in bytes.
+    pub tnvmcap: u128,
+    /// Unallocated NVM capacity in bytes.
+    pub unvmcap: u128,
+    /// ANA transition time in seconds.
+    pub anatt: u8,
+    /// Asymmetric namespace access capabilities.
+    pub anacap: u8,
+    /// ANA group identifier maximum.
+    pub anagrpmax: u32,
+    /// Number of ANA group identifiers.
+    pub nanagrpid: u32,
+    /// Number of namespaces.
+    pub nn: u32,
+    /// Optional NVM command support.
+    pub oncs: u16,
+    /// NVM subsystem NVMe qualified name.
+    pub subnqn: String,
+}
+
+impl NvmeControllerData {
+    /// Determines if the controller may be one of several controllers of
+    /// an NVM subsystem.
+    pub fn is_multi_ctrlr(&self) -> bool {
+        self.cmic & (1 << 1) != 0
+    }
+
+    /// Determines if the controller supports Asymmetric Namespace Access
+    /// reporting.
+    pub fn is_ana_reporting(&self) -> bool {
+        self.cmic & (1 << 3) != 0
+    }
+
+    /// Determines if the controller supports the Compare command.
+    pub fn supports_compare(&self) -> bool {
+        self.oncs & (1 << 0) != 0
+    }
+
+    /// Determines if the controller supports the Dataset Management command.
+    pub fn supports_dsm(&self) -> bool {
+        self.oncs & (1 << 2) != 0
+    }
+
+    /// Determines if the controller supports the Write Zeroes command.
+    pub fn supports_write_zeroes(&self) -> bool {
+        self.oncs & (1 << 3) != 0
+    }
+
+    /// Determines if the controller supports reservations.
+    pub fn supports_reservations(&self) -> bool {
+        self.oncs & (1 << 5) != 0
+    }
+
+    /// Determines if the controller supports the Copy command.
+    pub fn supports_copy(&self) -> bool {
+        self.oncs & (1 << 8) != 0
+    }
+
+    /// Determines if the controller reports the ANA Optimized state.
+    pub fn ana_optimized_state(&self) -> bool {
+        self.anacap & (1 << 0) != 0
+    }
+
+    /// Determines if the controller reports the ANA Non-Optimized state.
+    pub fn ana_non_optimized_state(&self) -> bool {
+        self.anacap & (1 << 1) != 0
+    }
+
+    /// Determines if the controller reports the ANA Inaccessible state.
+    pub fn ana_inaccessible_state(&self) -> bool {
+        self.anacap & (1 << 2) != 0
+    }
+}
+
+impl From<&spdk_nvme_ctrlr_data> for NvmeControllerData {
+    fn from(d: &spdk_nvme_ctrlr_data) -> Self {
+        // `spdk_nvme_ctrlr_data` is opaque to Rust: decode it from the
+        // byte offsets defined by the NVMe specification.
+        let b = unsafe {
+            from_raw_parts(
+                d as *const _ as *const u8,
+                size_of::<spdk_nvme_ctrlr_data>(),
+            )
+        };
+
+        Self {
+            vid: le16(b, 0),
+            ssvid: le16(b, 2),
+            serial_number: ascii(&b[4 .. 24]),
+            model_number: ascii(&b[24 .. 64]),
+            firmware_revision: ascii(&b[64 .. 72]),
+            ieee_oui: [b[73], b[74], b[75]],
+            cmic: b[76],
+            mdts: b[77],
+            cntlid: le16(b, 78),
+            version: le32(b, 80),
+            oacs: le16(b, 256),
+            tnvmcap: le128(b, 280),
+            unvmcap: le128(b, 296),
+            anatt: b[342],
+            anacap: b[343],
+            anagrpmax: le32(b, 344),
+            nanagrpid: le32(b, 348),
+            nn: le32(b, 516),
+            oncs: le16(b, 520),
+            subnqn: ascii(&b[768 .. 1024]),
+        }
+    }
+}
+
+/// LBA format of a namespace.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NvmeLbaFormat {
+    /// Metadata size in bytes.
+    pub metadata_size: u16,
+    /// Data size in bytes. Zero if the format is not supported.
+    pub data_size: u32,
+    /// Relative performance: 0 is the best, 3 is degraded.
+    pub relative_performance: u8,
+}
+
+/// Decoded Identify Namespace data structure (CNS 0x00).
+#[derive(Debug, Clone)]
+pub struct NvmeNamespaceData {
+    /// Namespace size in logical blocks.
+    pub size: u64,
+    /// Namespace capacity in logical blocks.
+    pub capacity: u64,
+    /// Namespace utilization in logical blocks.
+    pub utilization: u64,
+    /// NVM capacity in bytes.
+    pub nvm_capacity: u128,
+    /// Supported LBA formats.
+    pub lba_formats: Vec<NvmeLbaFormat>,
+    /// Index of the LBA format the namespace is formatted with.
+    pub lba_format_index: u8,
+    /// True if metadata is transferred at the end of the data LBA.
+    pub extended_lba: bool,
+    /// End-to-end data protection type; zero if disabled.
+    pub dif_type: u8,
+    /// True if protection information is transferred as the first bytes
+    /// of metadata.
+    pub dif_first: bool,
+    /// True if the namespace may be attached to several controllers.
+    pub shared: bool,
+    /// ANA group identifier.
+    pub ana_group_id: u32,
+    /// Namespace globally unique identifier.
+    pub nguid: [u8; 16],
+    /// IEEE extended unique identifier.
+    pub eui64: [u8; 8],
+}
+
+impl NvmeNamespaceData {
+    /// Returns the LBA format the namespace is formatted with.
+    pub fn lba_format(&self) -> Option<&NvmeLbaFormat> {
+        self.lba_formats.get(self.lba_format_index as usize)
+    }
+
+    /// Returns the logical block size in bytes, or zero if the current LBA
+    /// format is invalid.
+    pub fn block_size(&self) -> u32 {
+        self.lba_format().map_or(0, |f| f.data_size)
+    }
+}
+
+impl From<&spdk_nvme_ns_data> for NvmeNamespaceData {
+    fn from(d: &spdk_nvme_ns_data) -> Self {
+        let lba_formats = d
+            .lbaf
+            .iter()
+            .take(d.nlbaf as usize + 1)
+            .map(|f| NvmeLbaFormat {
+                metadata_size: f.ms() as u16,
+                data_size: match f.lbads() {
+                    0 => 0,
+                    n => 1u32 << n,
+                },
+                relative_performance: f.rp() as u8,
+            })
+            .collect();
+
+        Self {
+            size: d.nsze,
+            capacity: d.ncap,
+            utilization: d.nuse,
+            nvm_capacity: d.nvmcap[0] as u128 | (d.nvmcap[1] as u128) << 64,
+            lba_formats,
+            lba_format_index: d.flbas.format() | d.flbas.msb_format() << 4,
+            extended_lba: d.flbas.extended() != 0,
+            dif_type: d.dps.pit(),
+            dif_first: d.dps.md_start() != 0,
+            shared: d.nmic.can_share() != 0,
+            ana_group_id: d.anagrpid,
+            nguid: d.nguid,
+            eui64: d.eui64.to_le_bytes(),
+        }
+    }
+}
+
+/// Namespace identification descriptor (CNS 0x03).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvmeNsIdDescriptor {
+    /// IEEE extended unique identifier.
+    Eui64([u8; 8]),
+    /// Namespace globally unique identifier.
+    Nguid([u8; 16]),
+    /// Namespace UUID.
+    Uuid(uuid::Uuid),
+    /// Command set identifier.
+    Csi(u8),
+    /// Descriptor of unknown type.
+    Unknown(u8, Vec<u8>),
+}
+
+impl NvmeNsIdDescriptor {
+    /// Decodes a namespace identification descriptor list.
+    ///
+    /// # Arguments
+    ///
+    /// * `b`: Descriptor list data.
+    fn parse_list(b: &[u8]) -> Vec<Self> {
+        let mut res = Vec::new();
+        let mut pos = 0;
+
+        // Each descriptor is a 4-byte header followed by NIDL bytes of the
+        // identifier. The list is terminated by a zero-length descriptor.
+        while pos + 4 <= b.len() {
+            let nidt = b[pos];
+            let nidl = b[pos + 1] as usize;
+            if nidl == 0 || pos + 4 + nidl > b.len() {
+                break;
+            }
+
+            let nid = &b[pos + 4 .. pos + 4 + nidl];
+            res.push(match (nidt, nidl) {
+                (NIDT_EUI64, 8) => Self::Eui64(nid.try_into().unwrap()),
+                (NIDT_NGUID, 16) => Self::Nguid(nid.try_into().unwrap()),
+                (NIDT_UUID, 16) => {
+                    Self::Uuid(uuid::Uuid::from_bytes(nid.try_into().unwrap()))
+                }
+                (NIDT_CSI, 1) => Self::Csi(nid[0]),
+                _ => Self::Unknown(nidt, nid.to_vec()),
+            });
+
+            pos += 4 + nidl;
+        }
+
+        res
+    }
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Issues Identify Controller to the underlying NVMe controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    pub async fn identify_controller(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+    ) -> Result<NvmeControllerData, BdevDescError> {
+        let buf = self.identify(chan, nvme_identify_cns::CTRLR, 0).await?;
+        let d = unsafe { &*(buf.as_ptr() as *const spdk_nvme_ctrlr_data) };
+        Ok(NvmeControllerData::from(d))
+    }
+
+    /// Issues Identify Namespace to the underlying NVMe controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `nsid`: Namespace ID.
+    pub async fn identify_namespace(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        nsid: u32,
+    ) -> Result<NvmeNamespaceData, BdevDescError> {
+        let buf = self.identify(chan, nvme_identify_cns::NS, nsid).await?;
+        let d = unsafe { &*(buf.as_ptr() as *const spdk_nvme_ns_data) };
+        Ok(NvmeNamespaceData::from(d))
+    }
+
+    /// Returns up to 1024 active namespace IDs greater than the given one,
+    /// in increasing order.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `start_nsid`: Namespace ID to start after; zero to start from the
+    ///   first namespace.
+    pub async fn identify_active_namespaces(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        start_nsid: u32,
+    ) -> Result<Vec<u32>, BdevDescError> {
+        let buf = self
+            .identify(chan, nvme_identify_cns::ACTIVE_NS_LIST, start_nsid)
+            .await?;
+
+        Ok(buf
+            .as_slice()
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .take_while(|&nsid| nsid != 0)
+            .collect())
+    }
+
+    /// Returns the namespace identification descriptors of a namespace.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `nsid`: Namespace ID.
+    pub async fn identify_ns_id_descriptors(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        nsid: u32,
+    ) -> Result<Vec<NvmeNsIdDescriptor>, BdevDescError> {
+        let buf = self
+            .identify(chan, nvme_identify_cns::NS_ID_DESCRIPTOR_LIST, nsid)
+            .await?;
+
+        Ok(NvmeNsIdDescriptor::parse_list(buf.as_slice()))
+    }
+
+    /// Issues an Identify command as a read-only admin command, and returns
+    /// the data buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `cns`: Controller or Namespace Structure to identify.
+    /// * `nsid`: Namespace ID.
+    async fn identify(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cns: u8,
+        nsid: u32,
+    ) -> Result<DmaBuf, BdevDescError> {
+        let buf = self.alloc_io_buf(IDENTIFY_DATA_SIZE)?;
+
+        let cmd = NvmeCommand::new(nvme_admin_opc::IDENTIFY)
+            .with_nsid(nsid)
+            .with_cdw10(cns as u32)
+            .with_buf(buf);
+
+        let (_, mut cmd) = self.exec_nvme_admin(chan, cmd, true).await?;
+
+        Ok(cmd
+            .take_buf()
+            .expect("Identify data buffer must be returned"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Appends a namespace identification descriptor to a list.
+    fn push_desc(b: &mut Vec<u8>, nidt: u8, nid: &[u8]) {
+        b.extend([nidt, nid.len() as u8, 0, 0]);
+        b.extend(nid);
+    }
+
+    #[test]
+    fn ns_id_descriptors() {
+        let uuid = uuid::Uuid::from_bytes([0x11; 16]);
+        let mut b = Vec::new();
+        push_desc(&mut b, NIDT_EUI64, &[1; 8]);
+        push_desc(&mut b, NIDT_NGUID, &[2; 16]);
+        push_desc(&mut b, NIDT_UUID, uuid.as_bytes());
+        push_desc(&mut b, NIDT_CSI, &[0x2]);
+        push_desc(&mut b, 0x9, &[3; 3]);
+        b.resize(IDENTIFY_DATA_SIZE as usize, 0);
+
+        assert_eq!(
+            NvmeNsIdDescriptor::parse_list(&b),
+            vec![
+                NvmeNsIdDescriptor::Eui64([1; 8]),
+                NvmeNsIdDescriptor::Nguid([2; 16]),
+                NvmeNsIdDescriptor::Uuid(uuid),
+                NvmeNsIdDescriptor::Csi(0x2),
+                NvmeNsIdDescriptor::Unknown(0x9, vec![3; 3]),
+            ]
+        );
+    }
+
+    #[test]
+    fn ns_id_descriptors_bad_length() {
+        // A descriptor with an unexpected length is reported as unknown.
+        let mut b = Vec::new();
+        push_desc(&mut b, NIDT_EUI64, &[1; 4]);
+        assert_eq!(
+            NvmeNsIdDescriptor::parse_list(&b),
+            vec![NvmeNsIdDescriptor::Unknown(NIDT_EUI64, vec![1; 4])]
+        );
+
+        // A descriptor that does not fit into the data ends the list.
+        push_desc(&mut b, NIDT_NGUID, &[2; 16]);
+        b.truncate(b.len() - 1);
+        assert_eq!(NvmeNsIdDescriptor::parse_list(&b).len(), 1);
+    }
+}
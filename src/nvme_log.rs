@@ -0,0 +1,648 @@
+///! NVMe Get Log Page command and decoded log pages.
+use std::cmp::min;
+
+use crate::{
+    nvme::{ascii, le128, le16, le32, le64},
+    nvme_admin_opc,
+    nvme_log_page,
+    BdevDesc,
+    BdevDescError,
+    BdevOps,
+    IoChannel,
+    NvmeCommand,
+    NvmeStatus,
+};
+
+/// Namespace ID addressing all namespaces of a controller.
+const NVME_GLOBAL_NS_TAG: u32 = 0xffffffff;
+
+/// Maximum amount of log page data transferred by a single command.
+const LOG_PAGE_CHUNK_SIZE: u64 = 4096;
+
+/// Sizes of fixed-size log pages and entries.
+const SMART_LOG_SIZE: u64 = 512;
+const ERROR_LOG_ENTRY_SIZE: u64 = 64;
+const FW_SLOT_LOG_SIZE: u64 = 512;
+const CHANGED_NS_LIST_SIZE: u64 = 4096;
+
+/// Sizes of the ANA log page header and group descriptor header.
+const ANA_LOG_HDR_SIZE: usize = 16;
+const ANA_GROUP_HDR_SIZE: usize = 32;
+
+/// ANA log page specific field: return groups only, without namespace IDs.
+const ANA_LSP_RGO: u8 = 0x1;
+
+/// SMART / Health Information log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmeSmartLog {
+    /// Critical warning flags.
+    pub critical_warning: u8,
+    /// Composite temperature in Kelvin.
+    pub temperature: u16,
+    /// Available spare capacity, in percent.
+    pub available_spare: u8,
+    /// Available spare threshold, in percent.
+    pub available_spare_threshold: u8,
+    /// Vendor-specific estimate of the life used, in percent.
+    pub percentage_used: u8,
+    /// Number of 512-byte data units read, in thousands.
+    pub data_units_read: u128,
+    /// Number of 512-byte data units written, in thousands.
+    pub data_units_written: u128,
+    /// Number of read commands completed.
+    pub host_read_commands: u128,
+    /// Number of write commands completed.
+    pub host_write_commands: u128,
+    /// Time the controller was busy with I/O, in minutes.
+    pub controller_busy_time: u128,
+    /// Number of power cycles.
+    pub power_cycles: u128,
+    /// Number of power-on hours.
+    pub power_on_hours: u128,
+    /// Number of unsafe shutdowns.
+    pub unsafe_shutdowns: u128,
+    /// Number of unrecovered data integrity errors.
+    pub media_errors: u128,
+    /// Number of error information log entries over the controller's life.
+    pub num_error_info_log_entries: u128,
+    /// Time above the warning composite temperature threshold, in minutes.
+    pub warning_temp_time: u32,
+    /// Time above the critical composite temperature threshold, in minutes.
+    pub critical_temp_time: u32,
+    /// Temperature sensors in Kelvin; zero if not implemented.
+    pub temp_sensors: [u16; 8],
+}
+
+impl NvmeSmartLog {
+    /// Decodes a SMART / Health Information log page.
+    ///
+    /// # Arguments
+    ///
+    /// * `b`: Log page data.
+    fn parse(b: &[u8]) -> Self {
+        let mut temp_sensors = [0; 8];
+        temp_sensors
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, t)| *t = le16(b, 200 + i * 2));
+
+        Self {
+            critical_warning: b[0],
+            temperature: le16(b, 1),
+            available_spare: b[3],
+            available_spare_threshold: b[4],
+            percentage_used: b[5],
+            data_units_read: le128(b, 32),
+            data_units_written: le128(b, 48),
+            host_read_commands: le128(b, 64),
+            host_write_commands: le128(b, 80),
+            controller_busy_time: le128(b, 96),
+            power_cycles: le128(b, 112),
+            power_on_hours: le128(b, 128),
+            unsafe_shutdowns: le128(b, 144),
+            media_errors: le128(b, 160),
+            num_error_info_log_entries: le128(b, 176),
+            warning_temp_time: le32(b, 192),
+            critical_temp_time: le32(b, 196),
+            temp_sensors,
+        }
+    }
+}
+
+/// Error Information log page entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmeErrorLogEntry {
+    /// Unique error count; zero if the entry is not valid.
+    pub error_count: u64,
+    /// Submission queue ID of the failed command.
+    pub sqid: u16,
+    /// Command ID of the failed command.
+    pub cid: u16,
+    /// Status of the failed command.
+    pub status: NvmeStatus,
+    /// True if the command should not be retried.
+    pub dnr: bool,
+    /// Byte and bit of the command parameter that caused the error.
+    pub parameter_error_location: u16,
+    /// First LBA that experienced the error.
+    pub lba: u64,
+    /// Namespace ID of the failed command.
+    pub nsid: u32,
+    /// Command-specific information.
+    pub command_specific: u64,
+}
+
+impl NvmeErrorLogEntry {
+    /// Decodes an Error Information log page entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `b`: Entry data.
+    fn parse(b: &[u8]) -> Self {
+        // Status field: bit 0 is the phase tag, followed by the SC, SCT,
+        // CRD, More and DNR fields.
+        let sf = le16(b, 12);
+        let sc = (sf >> 1) & 0xff;
+        let sct = (sf >> 9) & 0x7;
+
+        Self {
+            error_count: le64(b, 0),
+            sqid: le16(b, 8),
+            cid: le16(b, 10),
+            status: NvmeStatus::from((sct as i32, sc as i32)),
+            dnr: sf & (1 << 15) != 0,
+            parameter_error_location: le16(b, 14),
+            lba: le64(b, 16),
+            nsid: le32(b, 24),
+            command_specific: le64(b, 32),
+        }
+    }
+}
+
+/// Firmware Slot Information log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmeFwSlotLog {
+    /// Slot of the active firmware (1-7).
+    pub active_slot: u8,
+    /// Slot of the firmware activated at the next reset; zero if not set.
+    pub next_reset_slot: u8,
+    /// Firmware revisions of slots 1 to 7; empty for empty slots.
+    pub revisions: Vec<String>,
+}
+
+impl NvmeFwSlotLog {
+    /// Decodes a Firmware Slot Information log page.
+    ///
+    /// # Arguments
+    ///
+    /// * `b`: Log page data.
+    fn parse(b: &[u8]) -> Self {
+        Self {
+            active_slot: b[0] & 0x7,
+            next_reset_slot: (b[0] >> 4) & 0x7,
+            revisions: b[8 .. 64].chunks_exact(8).map(ascii).collect(),
+        }
+    }
+}
+
+/// Changed Namespace List log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvmeChangedNsList {
+    /// IDs of the changed namespaces.
+    Namespaces(Vec<u32>),
+    /// More than 1024 namespaces have changed.
+    Overflow,
+}
+
+impl NvmeChangedNsList {
+    /// Decodes a Changed Namespace List log page.
+    ///
+    /// # Arguments
+    ///
+    /// * `b`: Log page data.
+    fn parse(b: &[u8]) -> Self {
+        if le32(b, 0) == NVME_GLOBAL_NS_TAG {
+            return Self::Overflow;
+        }
+
+        Self::Namespaces(
+            (0 .. b.len() / 4)
+                .map(|i| le32(b, i * 4))
+                .take_while(|&nsid| nsid != 0)
+                .collect(),
+        )
+    }
+}
+
+/// ANA state of a group.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NvmeAnaState {
+    Optimized,
+    NonOptimized,
+    Inaccessible,
+    PersistentLoss,
+    Change,
+    Unknown(u8),
+}
+
+impl From<u8> for NvmeAnaState {
+    fn from(s: u8) -> Self {
+        match s {
+            0x1 => Self::Optimized,
+            0x2 => Self::NonOptimized,
+            0x3 => Self::Inaccessible,
+            0x4 => Self::PersistentLoss,
+            0xf => Self::Change,
+            s => Self::Unknown(s),
+        }
+    }
+}
+
+/// ANA group descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmeAnaGroup {
+    /// ANA group ID.
+    pub ana_group_id: u32,
+    /// Change count of the group.
+    pub change_count: u64,
+    /// ANA state of the group.
+    pub state: NvmeAnaState,
+    /// IDs of the namespaces of the group; empty if the log page has been
+    /// read with groups only.
+    pub nsids: Vec<u32>,
+}
+
+/// Asymmetric Namespace Access log page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvmeAnaLog {
+    /// Change count of the log page.
+    pub change_count: u64,
+    /// ANA group descriptors.
+    pub groups: Vec<NvmeAnaGroup>,
+}
+
+impl NvmeAnaLog {
+    /// Decodes an ANA log page. Returns the number of bytes needed to hold
+    /// the whole log page if the given data is incomplete.
+    ///
+    /// # Arguments
+    ///
+    /// * `b`: Log page data.
+    fn parse(b: &[u8]) -> Result<Self, usize> {
+        if b.len() < ANA_LOG_HDR_SIZE {
+            return Err(ANA_LOG_HDR_SIZE);
+        }
+
+        let num_groups = le16(b, 8) as usize;
+        let mut groups = Vec::with_capacity(num_groups);
+        let mut pos = ANA_LOG_HDR_SIZE;
+
+        for _ in 0 .. num_groups {
+            if pos + ANA_GROUP_HDR_SIZE > b.len() {
+                return Err(pos + ANA_GROUP_HDR_SIZE);
+            }
+
+            let nnsid = le32(b, pos + 4) as usize;
+            let end = pos + ANA_GROUP_HDR_SIZE + nnsid * 4;
+            if end > b.len() {
+                return Err(end);
+            }
+
+            groups.push(NvmeAnaGroup {
+                ana_group_id: le32(b, pos),
+                change_count: le64(b, pos + 8),
+                state: NvmeAnaState::from(b[pos + 16] & 0xf),
+                nsids: (0 .. nnsid)
+                    .map(|i| le32(b, pos + ANA_GROUP_HDR_SIZE + i * 4))
+                    .collect(),
+            });
+
+            pos = end;
+        }
+
+        Ok(Self {
+            change_count: le64(b, 0),
+            groups,
+        })
+    }
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Reads a log page from the underlying NVMe controller.
+    /// Large reads are split into several Get Log Page commands.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `lid`: Log page identifier.
+    /// * `nsid`: Namespace ID; `0xffffffff` for controller-wide logs.
+    /// * `lsp`: Log specific field.
+    /// * `offset`: Offset in the log page, in bytes; must be a multiple of 4.
+    /// * `len`: Number of bytes to read; rounded up to a multiple of 4.
+    pub async fn get_log_page(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        lid: u8,
+        nsid: u32,
+        lsp: u8,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, BdevDescError> {
+        let len = (len + 3) & !3;
+        let mut res = Vec::with_capacity(len as usize);
+
+        while (res.len() as u64) < len {
+            let n = min(len - res.len() as u64, LOG_PAGE_CHUNK_SIZE);
+            let off = offset + res.len() as u64;
+            let numd = n / 4 - 1;
+
+            let buf = self.alloc_io_buf(n)?;
+
+            let cmd = NvmeCommand::new(nvme_admin_opc::GET_LOG_PAGE)
+                .with_nsid(nsid)
+                .with_cdw10(
+                    lid as u32
+                        | ((lsp as u32) & 0xf) << 8
+                        | ((numd as u32) & 0xffff) << 16,
+                )
+                .with_cdw11((numd >> 16) as u32 & 0xffff)
+                .with_cdw12(off as u32)
+                .with_cdw13((off >> 32) as u32)
+                .with_buf(buf);
+
+            let (_, cmd) = self.exec_nvme_admin(chan, cmd, true).await?;
+
+            res.extend_from_slice(cmd.into_data_buf().as_slice());
+        }
+
+        Ok(res)
+    }
+
+    /// Reads the SMART / Health Information log page of the controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    pub async fn get_smart_log(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+    ) -> Result<NvmeSmartLog, BdevDescError> {
+        let b = self
+            .get_log_page(
+                chan,
+                nvme_log_page::HEALTH_INFORMATION,
+                NVME_GLOBAL_NS_TAG,
+                0,
+                0,
+                SMART_LOG_SIZE,
+            )
+            .await?;
+
+        Ok(NvmeSmartLog::parse(&b))
+    }
+
+    /// Reads the given number of Error Information log page entries,
+    /// most recent first. Entries with a zero error count are not valid.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `num_entries`: Number of entries to read.
+    pub async fn get_error_log(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        num_entries: u32,
+    ) -> Result<Vec<NvmeErrorLogEntry>, BdevDescError> {
+        let b = self
+            .get_log_page(
+                chan,
+                nvme_log_page::ERROR,
+                NVME_GLOBAL_NS_TAG,
+                0,
+                0,
+                num_entries as u64 * ERROR_LOG_ENTRY_SIZE,
+            )
+            .await?;
+
+        Ok(b.chunks_exact(ERROR_LOG_ENTRY_SIZE as usize)
+            .map(NvmeErrorLogEntry::parse)
+            .collect())
+    }
+
+    /// Reads the Firmware Slot Information log page.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    pub async fn get_fw_slot_log(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+    ) -> Result<NvmeFwSlotLog, BdevDescError> {
+        let b = self
+            .get_log_page(
+                chan,
+                nvme_log_page::FIRMWARE_SLOT,
+                NVME_GLOBAL_NS_TAG,
+                0,
+                0,
+                FW_SLOT_LOG_SIZE,
+            )
+            .await?;
+
+        Ok(NvmeFwSlotLog::parse(&b))
+    }
+
+    /// Reads the Changed Namespace List log page.
+    /// Reading this log page clears it on the controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    pub async fn get_changed_ns_list(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+    ) -> Result<NvmeChangedNsList, BdevDescError> {
+        let b = self
+            .get_log_page(
+                chan,
+                nvme_log_page::CHANGED_NS_LIST,
+                NVME_GLOBAL_NS_TAG,
+                0,
+                0,
+                CHANGED_NS_LIST_SIZE,
+            )
+            .await?;
+
+        Ok(NvmeChangedNsList::parse(&b))
+    }
+
+    /// Reads the Asymmetric Namespace Access log page.
+    /// The page is read again with a larger size until all group
+    /// descriptors fit.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `groups_only`: Do not return namespace IDs of the groups.
+    pub async fn get_ana_log(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        groups_only: bool,
+    ) -> Result<NvmeAnaLog, BdevDescError> {
+        let lsp = if groups_only { ANA_LSP_RGO } else { 0 };
+        let mut len = LOG_PAGE_CHUNK_SIZE;
+
+        loop {
+            let b = self
+                .get_log_page(
+                    chan,
+                    nvme_log_page::ASYMMETRIC_NAMESPACE_ACCESS,
+                    NVME_GLOBAL_NS_TAG,
+                    lsp,
+                    0,
+                    len,
+                )
+                .await?;
+
+            match NvmeAnaLog::parse(&b) {
+                Ok(log) => return Ok(log),
+                Err(needed) => len = needed as u64,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libspdk::SPDK_NVME_SC_UNRECOVERED_READ_ERROR;
+
+    #[test]
+    fn smart_log() {
+        let mut b = vec![0u8; SMART_LOG_SIZE as usize];
+        b[0] = 0x4;
+        b[1 .. 3].copy_from_slice(&310u16.to_le_bytes());
+        b[3] = 100;
+        b[4] = 10;
+        b[5] = 3;
+        b[32 .. 48].copy_from_slice(&(1u128 << 64 | 7).to_le_bytes());
+        b[160 .. 176].copy_from_slice(&5u128.to_le_bytes());
+        b[196 .. 200].copy_from_slice(&9u32.to_le_bytes());
+        b[202 .. 204].copy_from_slice(&305u16.to_le_bytes());
+
+        let log = NvmeSmartLog::parse(&b);
+        assert_eq!(log.critical_warning, 0x4);
+        assert_eq!(log.temperature, 310);
+        assert_eq!(log.available_spare, 100);
+        assert_eq!(log.available_spare_threshold, 10);
+        assert_eq!(log.percentage_used, 3);
+        assert_eq!(log.data_units_read, 1 << 64 | 7);
+        assert_eq!(log.data_units_written, 0);
+        assert_eq!(log.media_errors, 5);
+        assert_eq!(log.critical_temp_time, 9);
+        assert_eq!(log.temp_sensors, [0, 305, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn error_log_entry() {
+        let mut b = vec![0u8; ERROR_LOG_ENTRY_SIZE as usize];
+        b[0 .. 8].copy_from_slice(&42u64.to_le_bytes());
+        b[8 .. 10].copy_from_slice(&1u16.to_le_bytes());
+        b[10 .. 12].copy_from_slice(&0x1234u16.to_le_bytes());
+        // Media error SCT (0x2), unrecovered read error SC (0x81), DNR.
+        let sf: u16 = 1 << 15 | 0x2 << 9 | 0x81 << 1 | 1;
+        b[12 .. 14].copy_from_slice(&sf.to_le_bytes());
+        b[16 .. 24].copy_from_slice(&0x1000u64.to_le_bytes());
+        b[24 .. 28].copy_from_slice(&1u32.to_le_bytes());
+
+        let e = NvmeErrorLogEntry::parse(&b);
+        assert_eq!(e.error_count, 42);
+        assert_eq!(e.sqid, 1);
+        assert_eq!(e.cid, 0x1234);
+        assert_eq!(
+            e.status,
+            NvmeStatus::Media(SPDK_NVME_SC_UNRECOVERED_READ_ERROR)
+        );
+        assert!(e.dnr);
+        assert_eq!(e.lba, 0x1000);
+        assert_eq!(e.nsid, 1);
+    }
+
+    #[test]
+    fn fw_slot_log() {
+        let mut b = vec![0u8; FW_SLOT_LOG_SIZE as usize];
+        b[0] = 0x2 << 4 | 0x1;
+        b[8 .. 16].copy_from_slice(b"1.0     ");
+        b[16 .. 24].copy_from_slice(b"2.0-rc1\0");
+
+        let log = NvmeFwSlotLog::parse(&b);
+        assert_eq!(log.active_slot, 1);
+        assert_eq!(log.next_reset_slot, 2);
+        assert_eq!(log.revisions.len(), 7);
+        assert_eq!(log.revisions[0], "1.0");
+        assert_eq!(log.revisions[1], "2.0-rc1");
+        assert!(log.revisions[2 ..].iter().all(String::is_empty));
+    }
+
+    #[test]
+    fn changed_ns_list() {
+        let mut b = vec![0u8; CHANGED_NS_LIST_SIZE as usize];
+        assert_eq!(
+            NvmeChangedNsList::parse(&b),
+            NvmeChangedNsList::Namespaces(Vec::new())
+        );
+
+        b[0 .. 4].copy_from_slice(&3u32.to_le_bytes());
+        b[4 .. 8].copy_from_slice(&7u32.to_le_bytes());
+        assert_eq!(
+            NvmeChangedNsList::parse(&b),
+            NvmeChangedNsList::Namespaces(vec![3, 7])
+        );
+
+        b[0 .. 4].copy_from_slice(&NVME_GLOBAL_NS_TAG.to_le_bytes());
+        assert_eq!(NvmeChangedNsList::parse(&b), NvmeChangedNsList::Overflow);
+    }
+
+    /// Makes an ANA log page with the given groups of
+    /// (group ID, state, namespace IDs).
+    fn ana_log_page(groups: &[(u32, u8, &[u32])]) -> Vec<u8> {
+        let mut b = vec![0u8; ANA_LOG_HDR_SIZE];
+        b[0 .. 8].copy_from_slice(&11u64.to_le_bytes());
+        b[8 .. 10].copy_from_slice(&(groups.len() as u16).to_le_bytes());
+
+        for (id, state, nsids) in groups {
+            let mut g = vec![0u8; ANA_GROUP_HDR_SIZE];
+            g[0 .. 4].copy_from_slice(&id.to_le_bytes());
+            g[4 .. 8].copy_from_slice(&(nsids.len() as u32).to_le_bytes());
+            g[8 .. 16].copy_from_slice(&5u64.to_le_bytes());
+            g[16] = *state;
+            b.extend(g);
+            nsids.iter().for_each(|n| b.extend(n.to_le_bytes()));
+        }
+
+        b
+    }
+
+    #[test]
+    fn ana_log() {
+        let b = ana_log_page(&[(1, 0x1, &[1, 2]), (2, 0x3, &[])]);
+
+        assert_eq!(
+            NvmeAnaLog::parse(&b),
+            Ok(NvmeAnaLog {
+                change_count: 11,
+                groups: vec![
+                    NvmeAnaGroup {
+                        ana_group_id: 1,
+                        change_count: 5,
+                        state: NvmeAnaState::Optimized,
+                        nsids: vec![1, 2],
+                    },
+                    NvmeAnaGroup {
+                        ana_group_id: 2,
+                        change_count: 5,
+                        state: NvmeAnaState::Inaccessible,
+                        nsids: Vec::new(),
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn ana_log_incomplete() {
+        let b = ana_log_page(&[(1, 0x2, &[1, 2, 3])]);
+        let len = b.len();
+
+        assert_eq!(NvmeAnaLog::parse(&b[.. 8]), Err(ANA_LOG_HDR_SIZE));
+        assert_eq!(
+            NvmeAnaLog::parse(&b[.. ANA_LOG_HDR_SIZE]),
+            Err(ANA_LOG_HDR_SIZE + ANA_GROUP_HDR_SIZE)
+        );
+        assert_eq!(NvmeAnaLog::parse(&b[.. len - 4]), Err(len));
+        assert!(NvmeAnaLog::parse(&b).is_ok());
+    }
+}
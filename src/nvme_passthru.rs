@@ -0,0 +1,423 @@
+///! NVMe passthrough commands submitted via `BdevDesc<>`.
+use std::{fmt, mem::zeroed, os::raw::c_void, ptr::null_mut};
+
+use crate::{
+    bdev_desc_io::IoCompletion,
+    libspdk::{
+        nvme_cmd_cdw10_get,
+        nvme_cmd_cdw10_get_val,
+        nvme_cmd_cdw11_get,
+        nvme_cmd_cdw11_get_val,
+        spdk_bdev_nvme_admin_passthru,
+        spdk_bdev_nvme_admin_passthru_ro,
+        spdk_bdev_nvme_io_passthru,
+        spdk_bdev_nvme_io_passthru_md,
+        spdk_nvme_cmd,
+    },
+    BdevDesc,
+    BdevDescError,
+    BdevOps,
+    DmaBuf,
+    IoChannel,
+    IoType,
+    NvmeStatus,
+};
+
+/// NVMe command to be submitted as an admin or I/O passthrough command.
+/// Data and metadata buffers are owned by the command, and the command is
+/// owned by the I/O until it completes. Once completed, the command is
+/// given back with its buffers.
+pub struct NvmeCommand {
+    /// SPDK NVMe command.
+    cmd: spdk_nvme_cmd,
+    /// Data buffer.
+    buf: Option<DmaBuf>,
+    /// Metadata buffer, for I/O commands only.
+    md_buf: Option<DmaBuf>,
+}
+
+impl NvmeCommand {
+    /// Creates a new NVMe command with the given opcode.
+    /// All other command fields are zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `opc`: Command opcode.
+    pub fn new(opc: u8) -> Self {
+        let mut cmd: spdk_nvme_cmd = unsafe { zeroed() };
+        cmd.set_opc(opc as u16);
+
+        Self {
+            cmd,
+            buf: None,
+            md_buf: None,
+        }
+    }
+
+    /// Sets the namespace identifier.
+    pub fn with_nsid(mut self, nsid: u32) -> Self {
+        self.cmd.nsid = nsid;
+        self
+    }
+
+    /// Sets command dword 10.
+    pub fn with_cdw10(mut self, val: u32) -> Self {
+        unsafe { *nvme_cmd_cdw10_get(&mut self.cmd) = val };
+        self
+    }
+
+    /// Sets command dword 11.
+    pub fn with_cdw11(mut self, val: u32) -> Self {
+        unsafe { *nvme_cmd_cdw11_get(&mut self.cmd) = val };
+        self
+    }
+
+    /// Sets command dword 12.
+    pub fn with_cdw12(mut self, val: u32) -> Self {
+        self.cmd.cdw12 = val;
+        self
+    }
+
+    /// Sets command dword 13.
+    pub fn with_cdw13(mut self, val: u32) -> Self {
+        self.cmd.cdw13 = val;
+        self
+    }
+
+    /// Sets command dword 14.
+    pub fn with_cdw14(mut self, val: u32) -> Self {
+        self.cmd.cdw14 = val;
+        self
+    }
+
+    /// Sets command dword 15.
+    pub fn with_cdw15(mut self, val: u32) -> Self {
+        self.cmd.cdw15 = val;
+        self
+    }
+
+    /// Sets the data buffer. Its whole length is transferred.
+    pub fn with_buf(mut self, buf: DmaBuf) -> Self {
+        self.buf = Some(buf);
+        self
+    }
+
+    /// Sets the metadata buffer. Only I/O commands can transfer metadata.
+    pub fn with_md_buf(mut self, md_buf: DmaBuf) -> Self {
+        self.md_buf = Some(md_buf);
+        self
+    }
+
+    /// Returns the command opcode.
+    pub fn opc(&self) -> u8 {
+        self.cmd.opc() as u8
+    }
+
+    /// Returns the namespace identifier.
+    pub fn nsid(&self) -> u32 {
+        self.cmd.nsid
+    }
+
+    /// Returns command dword 10.
+    pub fn cdw10(&self) -> u32 {
+        unsafe { nvme_cmd_cdw10_get_val(&self.cmd) }
+    }
+
+    /// Returns command dword 11.
+    pub fn cdw11(&self) -> u32 {
+        unsafe { nvme_cmd_cdw11_get_val(&self.cmd) }
+    }
+
+    /// Returns the data buffer, if any.
+    pub fn buf(&self) -> Option<&DmaBuf> {
+        self.buf.as_ref()
+    }
+
+    /// Returns the metadata buffer, if any.
+    pub fn md_buf(&self) -> Option<&DmaBuf> {
+        self.md_buf.as_ref()
+    }
+
+    /// Takes the data buffer out of the command, e.g. once completed.
+    pub fn take_buf(&mut self) -> Option<DmaBuf> {
+        self.buf.take()
+    }
+
+    /// Takes the metadata buffer out of the command, e.g. once completed.
+    pub fn take_md_buf(&mut self) -> Option<DmaBuf> {
+        self.md_buf.take()
+    }
+
+    /// Consumes a command submitted with a data buffer, and returns the
+    /// buffer.
+    pub(crate) fn into_data_buf(mut self) -> DmaBuf {
+        self.buf.take().expect("NVMe command has no data buffer")
+    }
+
+    /// Returns a reference to the underlying SPDK NVMe command.
+    pub fn as_spdk_cmd(&self) -> &spdk_nvme_cmd {
+        &self.cmd
+    }
+
+    /// Returns the data buffer pointer and length.
+    fn buf_ptr(&mut self) -> (*mut c_void, u64) {
+        match self.buf.as_mut() {
+            Some(b) => (b.as_mut_ptr(), b.len()),
+            None => (null_mut(), 0),
+        }
+    }
+
+    /// Returns the metadata buffer pointer and length.
+    fn md_buf_ptr(&mut self) -> (*mut c_void, u64) {
+        match self.md_buf.as_mut() {
+            Some(b) => (b.as_mut_ptr(), b.len()),
+            None => (null_mut(), 0),
+        }
+    }
+}
+
+impl fmt::Debug for NvmeCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NvmeCommand")
+            .field("opc", &self.opc())
+            .field("nsid", &self.nsid())
+            .field("cdw10", &self.cdw10())
+            .field("cdw11", &self.cdw11())
+            .field("cdw12", &self.cmd.cdw12)
+            .field("cdw13", &self.cmd.cdw13)
+            .field("cdw14", &self.cmd.cdw14)
+            .field("cdw15", &self.cmd.cdw15)
+            .finish()
+    }
+}
+
+/// Completion of an NVMe passthrough command.
+#[derive(Debug, Copy, Clone)]
+pub struct NvmeCompletion {
+    /// Command-specific dword 0 of the completion.
+    cdw0: u32,
+    /// Completion status.
+    status: NvmeStatus,
+}
+
+impl NvmeCompletion {
+    /// Returns command-specific dword 0 of the completion.
+    pub fn cdw0(&self) -> u32 {
+        self.cdw0
+    }
+
+    /// Returns the completion status.
+    pub fn status(&self) -> NvmeStatus {
+        self.status
+    }
+
+    /// Determines if the command has succeeded.
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+}
+
+impl From<IoCompletion> for NvmeCompletion {
+    fn from(cpl: IoCompletion) -> Self {
+        Self {
+            cdw0: cpl.cdw0,
+            status: cpl.nvme_status,
+        }
+    }
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Submits an NVMe admin command to the underlying controller.
+    /// The descriptor must be opened for writing.
+    ///
+    /// A command that completes with an NVMe error is not an error: its
+    /// status is returned in the `NvmeCompletion`, along with the command
+    /// and its buffers. An error is returned if the command cannot be
+    /// submitted.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `cmd`: NVMe admin command.
+    pub async fn nvme_admin(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cmd: NvmeCommand,
+    ) -> Result<(NvmeCompletion, NvmeCommand), BdevDescError> {
+        self.submit_nvme_admin(chan, cmd, false)
+            .await
+            .map(|(cpl, cmd)| (cpl.into(), cmd))
+    }
+
+    /// Submits a read-only NVMe admin command (e.g. Identify) to the
+    /// underlying controller. Unlike `nvme_admin()`, this can be used with
+    /// descriptors opened without write access. The command is returned
+    /// along with the completion.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `cmd`: NVMe admin command.
+    pub async fn nvme_admin_ro(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cmd: NvmeCommand,
+    ) -> Result<(NvmeCompletion, NvmeCommand), BdevDescError> {
+        self.submit_nvme_admin(chan, cmd, true)
+            .await
+            .map(|(cpl, cmd)| (cpl.into(), cmd))
+    }
+
+    /// Submits an NVMe I/O command to the underlying namespace.
+    /// If the command has a metadata buffer, it is submitted with
+    /// separate metadata.
+    ///
+    /// A command that completes with an NVMe error is not an error: its
+    /// status is returned in the `NvmeCompletion`, along with the command
+    /// and its buffers. An error is returned if the command cannot be
+    /// submitted.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `cmd`: NVMe I/O command.
+    pub async fn nvme_io(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cmd: NvmeCommand,
+    ) -> Result<(NvmeCompletion, NvmeCommand), BdevDescError> {
+        self.submit_nvme_io(chan, cmd)
+            .await
+            .map(|(cpl, cmd)| (cpl.into(), cmd))
+    }
+
+    /// Submits an NVMe admin command, and fails if it completes with an
+    /// error. The command is returned along with the completion.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `cmd`: NVMe admin command.
+    /// * `read_only`: Submit as a read-only command.
+    pub(crate) async fn exec_nvme_admin(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cmd: NvmeCommand,
+        read_only: bool,
+    ) -> Result<(NvmeCompletion, NvmeCommand), BdevDescError> {
+        let (cpl, cmd) = self.submit_nvme_admin(chan, cmd, read_only).await?;
+        self.check_completion(IoType::NvmeAdmin, cpl)
+            .map(|cpl| (cpl.into(), cmd))
+    }
+
+    /// Submits an NVMe I/O command, and fails if it completes with an error.
+    /// The command is returned along with the completion.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `cmd`: NVMe I/O command.
+    pub(crate) async fn exec_nvme_io(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cmd: NvmeCommand,
+    ) -> Result<(NvmeCompletion, NvmeCommand), BdevDescError> {
+        let io_type = if cmd.md_buf.is_some() {
+            IoType::NvmeIoMd
+        } else {
+            IoType::NvmeIo
+        };
+        let (cpl, cmd) = self.submit_nvme_io(chan, cmd).await?;
+        self.check_completion(io_type, cpl)
+            .map(|cpl| (cpl.into(), cmd))
+    }
+
+    /// Submits an NVMe admin command and waits for its completion.
+    /// The command is owned by the I/O until it completes.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `cmd`: NVMe admin command.
+    /// * `read_only`: Submit as a read-only command.
+    async fn submit_nvme_admin(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cmd: NvmeCommand,
+        read_only: bool,
+    ) -> Result<(IoCompletion, NvmeCommand), BdevDescError> {
+        let f = if read_only {
+            spdk_bdev_nvme_admin_passthru_ro
+        } else {
+            spdk_bdev_nvme_admin_passthru
+        };
+
+        self.submit_io_raw_with(IoType::NvmeAdmin, cmd, |cmd, cb, arg| unsafe {
+            let (buf, nbytes) = cmd.buf_ptr();
+            f(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                &cmd.cmd,
+                buf,
+                nbytes,
+                cb,
+                arg,
+            )
+        })
+        .await
+    }
+
+    /// Submits an NVMe I/O command and waits for its completion.
+    /// The command is owned by the I/O until it completes.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `cmd`: NVMe I/O command.
+    async fn submit_nvme_io(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        cmd: NvmeCommand,
+    ) -> Result<(IoCompletion, NvmeCommand), BdevDescError> {
+        if cmd.md_buf.is_none() {
+            return self
+                .submit_io_raw_with(
+                    IoType::NvmeIo,
+                    cmd,
+                    |cmd, cb, arg| unsafe {
+                        let (buf, nbytes) = cmd.buf_ptr();
+                        spdk_bdev_nvme_io_passthru(
+                            self.as_ptr(),
+                            chan.legacy_as_ptr(),
+                            &cmd.cmd,
+                            buf,
+                            nbytes,
+                            cb,
+                            arg,
+                        )
+                    },
+                )
+                .await;
+        }
+
+        self.submit_io_raw_with(IoType::NvmeIoMd, cmd, |cmd, cb, arg| unsafe {
+            let (buf, nbytes) = cmd.buf_ptr();
+            let (md_buf, md_len) = cmd.md_buf_ptr();
+            spdk_bdev_nvme_io_passthru_md(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                &cmd.cmd,
+                buf,
+                nbytes,
+                md_buf,
+                md_len,
+                cb,
+                arg,
+            )
+        })
+        .await
+    }
+}
@@ -0,0 +1,551 @@
+///! NVMe reservations over I/O passthrough.
+use std::convert::TryInto;
+
+use crate::{
+    nvme_nvm_opcode,
+    nvme_reservation_acquire_action,
+    nvme_reservation_register_action,
+    nvme_reservation_register_cptpl,
+    nvme_reservation_release_action,
+    nvme_reservation_type,
+    BdevDesc,
+    BdevDescError,
+    BdevOps,
+    IoChannel,
+    NvmeCommand,
+};
+
+/// Initial size of a reservation report buffer.
+const REPORT_BUF_SIZE: u64 = 4096;
+
+/// Size of the reservation report header, and of a registrant entry,
+/// for the regular and the extended data structures.
+const REPORT_HDR_SIZE: usize = 24;
+const REPORT_ENTRY_SIZE: usize = 24;
+const REPORT_EXT_HDR_SIZE: usize = 64;
+const REPORT_EXT_ENTRY_SIZE: usize = 64;
+
+/// Reservation type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReservationType {
+    WriteExclusive,
+    ExclusiveAccess,
+    WriteExclusiveRegOnly,
+    ExclusiveAccessRegOnly,
+    WriteExclusiveAllRegs,
+    ExclusiveAccessAllRegs,
+}
+
+impl From<ReservationType> for u8 {
+    fn from(t: ReservationType) -> Self {
+        use nvme_reservation_type::*;
+        match t {
+            ReservationType::WriteExclusive => WRITE_EXCLUSIVE,
+            ReservationType::ExclusiveAccess => EXCLUSIVE_ACCESS,
+            ReservationType::WriteExclusiveRegOnly => WRITE_EXCLUSIVE_REG_ONLY,
+            ReservationType::ExclusiveAccessRegOnly => {
+                EXCLUSIVE_ACCESS_REG_ONLY
+            }
+            ReservationType::WriteExclusiveAllRegs => WRITE_EXCLUSIVE_ALL_REGS,
+            ReservationType::ExclusiveAccessAllRegs => {
+                EXCLUSIVE_ACCESS_ALL_REGS
+            }
+        }
+    }
+}
+
+impl ReservationType {
+    /// Converts a raw reservation type into `ReservationType`.
+    /// Returns `None` for zero (no reservation) and unknown values.
+    fn from_raw(t: u8) -> Option<Self> {
+        use nvme_reservation_type::*;
+        match t {
+            WRITE_EXCLUSIVE => Some(Self::WriteExclusive),
+            EXCLUSIVE_ACCESS => Some(Self::ExclusiveAccess),
+            WRITE_EXCLUSIVE_REG_ONLY => Some(Self::WriteExclusiveRegOnly),
+            EXCLUSIVE_ACCESS_REG_ONLY => Some(Self::ExclusiveAccessRegOnly),
+            WRITE_EXCLUSIVE_ALL_REGS => Some(Self::WriteExclusiveAllRegs),
+            EXCLUSIVE_ACCESS_ALL_REGS => Some(Self::ExclusiveAccessAllRegs),
+            _ => None,
+        }
+    }
+}
+
+/// Reservation register action.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReservationRegisterAction {
+    /// Registers a new key.
+    RegisterKey,
+    /// Unregisters the current key.
+    UnregisterKey,
+    /// Replaces the current key with a new one.
+    ReplaceKey,
+}
+
+impl From<ReservationRegisterAction> for u8 {
+    fn from(a: ReservationRegisterAction) -> Self {
+        use nvme_reservation_register_action::*;
+        match a {
+            ReservationRegisterAction::RegisterKey => REGISTER_KEY,
+            ReservationRegisterAction::UnregisterKey => UNREGISTER_KEY,
+            ReservationRegisterAction::ReplaceKey => REPLACE_KEY,
+        }
+    }
+}
+
+/// Change Persist Through Power Loss state, for reservation register.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReservationCptpl {
+    /// Leaves the PTPL state unchanged.
+    NoChanges,
+    /// Reservations are released and registrants cleared on power on.
+    ClearPowerOn,
+    /// Reservations and registrants persist across power loss.
+    PersistPowerLoss,
+}
+
+impl From<ReservationCptpl> for u8 {
+    fn from(c: ReservationCptpl) -> Self {
+        use nvme_reservation_register_cptpl::*;
+        match c {
+            ReservationCptpl::NoChanges => NO_CHANGES,
+            ReservationCptpl::ClearPowerOn => CLEAR_POWER_ON,
+            ReservationCptpl::PersistPowerLoss => PERSIST_POWER_LOSS,
+        }
+    }
+}
+
+/// Reservation acquire action.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReservationAcquireAction {
+    /// Acquires a reservation.
+    Acquire,
+    /// Preempts the reservation or registrations of another key.
+    Preempt,
+    /// Preempts, and aborts the commands of the preempted registrants.
+    PreemptAbort,
+}
+
+impl From<ReservationAcquireAction> for u8 {
+    fn from(a: ReservationAcquireAction) -> Self {
+        use nvme_reservation_acquire_action::*;
+        match a {
+            ReservationAcquireAction::Acquire => ACQUIRE,
+            ReservationAcquireAction::Preempt => PREEMPT,
+            ReservationAcquireAction::PreemptAbort => PREEMPT_ABORT,
+        }
+    }
+}
+
+/// Registrant of a namespace, as returned by reservation report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReservationRegistrant {
+    /// Controller ID, or 0xffff if the controller is not connected.
+    pub cntlid: u16,
+    /// True if this registrant holds the reservation.
+    pub holder: bool,
+    /// Host identifier: 8 bytes for the regular report,
+    /// 16 bytes for the extended one.
+    pub host_id: Vec<u8>,
+    /// Reservation key.
+    pub rkey: u64,
+}
+
+/// Parsed reservation report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReservationReport {
+    /// Reservation generation counter.
+    pub generation: u32,
+    /// Current reservation type, or `None` if there is no reservation.
+    pub rtype: Option<ReservationType>,
+    /// True if reservations persist through power loss.
+    pub ptpl: bool,
+    /// Registrants of the namespace.
+    pub registrants: Vec<ReservationRegistrant>,
+}
+
+impl ReservationReport {
+    /// Returns the registrant holding the reservation, if any.
+    /// For all-registrants reservation types, every registrant is a holder,
+    /// and the first one is returned.
+    pub fn holder(&self) -> Option<&ReservationRegistrant> {
+        self.registrants.iter().find(|r| r.holder)
+    }
+
+    /// Returns the number of registrants reported in the header of a
+    /// report.
+    ///
+    /// # Arguments
+    ///
+    /// * `b`: Report data.
+    fn num_registrants(b: &[u8]) -> usize {
+        u16::from_le_bytes([b[5], b[6]]) as usize
+    }
+
+    /// Returns the size of a report with the given number of registrants.
+    ///
+    /// # Arguments
+    ///
+    /// * `n`: Number of registrants.
+    /// * `extended`: Extended data structure.
+    fn data_size(n: usize, extended: bool) -> usize {
+        if extended {
+            REPORT_EXT_HDR_SIZE + n * REPORT_EXT_ENTRY_SIZE
+        } else {
+            REPORT_HDR_SIZE + n * REPORT_ENTRY_SIZE
+        }
+    }
+
+    /// Decodes a reservation report. Registrants that do not fit into the
+    /// data are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `b`: Report data.
+    /// * `extended`: Extended data structure.
+    fn parse(b: &[u8], extended: bool) -> Self {
+        let (hdr_size, entry_size) = if extended {
+            (REPORT_EXT_HDR_SIZE, REPORT_EXT_ENTRY_SIZE)
+        } else {
+            (REPORT_HDR_SIZE, REPORT_ENTRY_SIZE)
+        };
+
+        let registrants = b[hdr_size ..]
+            .chunks_exact(entry_size)
+            .take(Self::num_registrants(b))
+            .map(|e| {
+                let (rkey, host_id) = if extended {
+                    (&e[8 .. 16], &e[16 .. 32])
+                } else {
+                    (&e[16 .. 24], &e[8 .. 16])
+                };
+
+                ReservationRegistrant {
+                    cntlid: u16::from_le_bytes([e[0], e[1]]),
+                    holder: e[2] & 1 != 0,
+                    host_id: host_id.to_vec(),
+                    rkey: u64::from_le_bytes(rkey.try_into().unwrap()),
+                }
+            })
+            .collect();
+
+        Self {
+            generation: u32::from_le_bytes(b[0 .. 4].try_into().unwrap()),
+            rtype: ReservationType::from_raw(b[4]),
+            ptpl: b[9] & 1 != 0,
+            registrants,
+        }
+    }
+}
+
+/// NVMe reservation operations on the namespace of a bdev.
+/// Commands are submitted as NVMe I/O passthrough, and therefore the
+/// descriptor must be opened for writing.
+/// Reservation conflicts are reported as `BdevDescError::IoFailed` with
+/// the corresponding NVMe status.
+///
+/// # Generic Arguments
+///
+/// * `BdevData`: Bdev data type.
+pub struct Reservation<'a, BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Bdev descriptor.
+    desc: &'a BdevDesc<BdevData>,
+    /// I/O channel of the current thread.
+    chan: &'a IoChannel<BdevData::ChannelData>,
+}
+
+impl<'a, BdevData> Reservation<'a, BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Registers, unregisters or replaces a reservation key.
+    ///
+    /// # Arguments
+    ///
+    /// * `action`: Register action.
+    /// * `crkey`: Current reservation key.
+    /// * `nrkey`: New reservation key.
+    /// * `iekey`: Ignore the current key.
+    /// * `cptpl`: Change Persist Through Power Loss state.
+    pub async fn register(
+        &self,
+        action: ReservationRegisterAction,
+        crkey: u64,
+        nrkey: u64,
+        iekey: bool,
+        cptpl: ReservationCptpl,
+    ) -> Result<(), BdevDescError> {
+        let cdw10 = u8::from(action) as u32
+            | (iekey as u32) << 3
+            | (u8::from(cptpl) as u32) << 30;
+
+        self.exec(
+            nvme_nvm_opcode::RESERVATION_REGISTER,
+            cdw10,
+            &[crkey, nrkey],
+        )
+        .await
+    }
+
+    /// Acquires a reservation, or preempts another registrant.
+    ///
+    /// # Arguments
+    ///
+    /// * `action`: Acquire action.
+    /// * `rtype`: Reservation type.
+    /// * `crkey`: Current reservation key.
+    /// * `prkey`: Preempt reservation key; ignored for `Acquire`.
+    /// * `iekey`: Ignore the current key.
+    pub async fn acquire(
+        &self,
+        action: ReservationAcquireAction,
+        rtype: ReservationType,
+        crkey: u64,
+        prkey: u64,
+        iekey: bool,
+    ) -> Result<(), BdevDescError> {
+        let cdw10 = u8::from(action) as u32
+            | (iekey as u32) << 3
+            | (u8::from(rtype) as u32) << 8;
+
+        self.exec(nvme_nvm_opcode::RESERVATION_ACQUIRE, cdw10, &[crkey, prkey])
+            .await
+    }
+
+    /// Releases a reservation held by this host.
+    ///
+    /// # Arguments
+    ///
+    /// * `rtype`: Type of the reservation to release.
+    /// * `crkey`: Current reservation key.
+    /// * `iekey`: Ignore the current key.
+    pub async fn release(
+        &self,
+        rtype: ReservationType,
+        crkey: u64,
+        iekey: bool,
+    ) -> Result<(), BdevDescError> {
+        let cdw10 = nvme_reservation_release_action::RELEASE as u32
+            | (iekey as u32) << 3
+            | (u8::from(rtype) as u32) << 8;
+
+        self.exec(nvme_nvm_opcode::RESERVATION_RELEASE, cdw10, &[crkey])
+            .await
+    }
+
+    /// Releases the reservation and removes all registrants.
+    ///
+    /// # Arguments
+    ///
+    /// * `crkey`: Current reservation key.
+    /// * `iekey`: Ignore the current key.
+    pub async fn clear(
+        &self,
+        crkey: u64,
+        iekey: bool,
+    ) -> Result<(), BdevDescError> {
+        let cdw10 =
+            nvme_reservation_release_action::CLEAR as u32 | (iekey as u32) << 3;
+
+        self.exec(nvme_nvm_opcode::RESERVATION_RELEASE, cdw10, &[crkey])
+            .await
+    }
+
+    /// Reads the reservation status of the namespace.
+    ///
+    /// # Arguments
+    ///
+    /// * `extended`: Request the extended data structure, which reports 128-bit
+    ///   host identifiers.
+    pub async fn report(
+        &self,
+        extended: bool,
+    ) -> Result<ReservationReport, BdevDescError> {
+        let mut size = REPORT_BUF_SIZE;
+
+        loop {
+            let buf = self.desc.alloc_io_buf(size)?;
+
+            let cmd = NvmeCommand::new(nvme_nvm_opcode::RESERVATION_REPORT)
+                .with_cdw10((size / 4 - 1) as u32)
+                .with_cdw11(extended as u32)
+                .with_buf(buf);
+
+            let (_, cmd) = self.desc.exec_nvme_io(self.chan, cmd).await?;
+
+            // Retry with a larger buffer if not all registrants fit.
+            let buf = cmd.into_data_buf();
+            let b = buf.as_slice();
+            let n = ReservationReport::num_registrants(b);
+            let needed = ReservationReport::data_size(n, extended) as u64;
+            if needed <= size {
+                return Ok(ReservationReport::parse(b, extended));
+            }
+
+            size = (needed + 3) & !3;
+        }
+    }
+
+    /// Submits a reservation command with the given keys as data.
+    ///
+    /// # Arguments
+    ///
+    /// * `opc`: Command opcode.
+    /// * `cdw10`: Command dword 10.
+    /// * `keys`: Keys to transfer as command data.
+    async fn exec(
+        &self,
+        opc: u8,
+        cdw10: u32,
+        keys: &[u64],
+    ) -> Result<(), BdevDescError> {
+        let mut buf = self.desc.alloc_io_buf(keys.len() as u64 * 8)?;
+
+        buf.as_mut_slice()
+            .chunks_exact_mut(8)
+            .zip(keys)
+            .for_each(|(c, k)| c.copy_from_slice(&k.to_le_bytes()));
+
+        let cmd = NvmeCommand::new(opc).with_cdw10(cdw10).with_buf(buf);
+
+        self.desc.exec_nvme_io(self.chan, cmd).await.map(|_| ())
+    }
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Returns an object to manage NVMe reservations of the namespace of
+    /// this descriptor's bdev.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    pub fn reservation<'a>(
+        &'a self,
+        chan: &'a IoChannel<BdevData::ChannelData>,
+    ) -> Reservation<'a, BdevData> {
+        Reservation {
+            desc: self,
+            chan,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes a reservation report header.
+    fn report_hdr(size: usize, rtype: u8, num_regs: u16) -> Vec<u8> {
+        let mut b = vec![0u8; size];
+        b[0 .. 4].copy_from_slice(&3u32.to_le_bytes());
+        b[4] = rtype;
+        b[5 .. 7].copy_from_slice(&num_regs.to_le_bytes());
+        b[9] = 1;
+        b
+    }
+
+    #[test]
+    fn report() {
+        let mut b = report_hdr(
+            ReservationReport::data_size(2, false),
+            nvme_reservation_type::WRITE_EXCLUSIVE,
+            2,
+        );
+
+        let e = &mut b[REPORT_HDR_SIZE ..];
+        e[0 .. 2].copy_from_slice(&1u16.to_le_bytes());
+        e[8 .. 16].copy_from_slice(&[0xaa; 8]);
+        e[16 .. 24].copy_from_slice(&0x1111u64.to_le_bytes());
+
+        let e = &mut b[REPORT_HDR_SIZE + REPORT_ENTRY_SIZE ..];
+        e[0 .. 2].copy_from_slice(&0xffffu16.to_le_bytes());
+        e[2] = 1;
+        e[8 .. 16].copy_from_slice(&[0xbb; 8]);
+        e[16 .. 24].copy_from_slice(&0x2222u64.to_le_bytes());
+
+        let r = ReservationReport::parse(&b, false);
+        assert_eq!(ReservationReport::num_registrants(&b), 2);
+        assert_eq!(r.generation, 3);
+        assert_eq!(r.rtype, Some(ReservationType::WriteExclusive));
+        assert!(r.ptpl);
+        assert_eq!(
+            r.registrants,
+            vec![
+                ReservationRegistrant {
+                    cntlid: 1,
+                    holder: false,
+                    host_id: vec![0xaa; 8],
+                    rkey: 0x1111,
+                },
+                ReservationRegistrant {
+                    cntlid: 0xffff,
+                    holder: true,
+                    host_id: vec![0xbb; 8],
+                    rkey: 0x2222,
+                },
+            ]
+        );
+        assert_eq!(r.holder().map(|h| h.rkey), Some(0x2222));
+    }
+
+    #[test]
+    fn report_extended() {
+        let mut b = report_hdr(
+            ReservationReport::data_size(1, true),
+            nvme_reservation_type::EXCLUSIVE_ACCESS_ALL_REGS,
+            1,
+        );
+
+        let e = &mut b[REPORT_EXT_HDR_SIZE ..];
+        e[0 .. 2].copy_from_slice(&2u16.to_le_bytes());
+        e[2] = 1;
+        e[8 .. 16].copy_from_slice(&0x3333u64.to_le_bytes());
+        e[16 .. 32].copy_from_slice(&[0xcc; 16]);
+
+        let r = ReservationReport::parse(&b, true);
+        assert_eq!(r.rtype, Some(ReservationType::ExclusiveAccessAllRegs));
+        assert_eq!(
+            r.registrants,
+            vec![ReservationRegistrant {
+                cntlid: 2,
+                holder: true,
+                host_id: vec![0xcc; 16],
+                rkey: 0x3333,
+            }]
+        );
+    }
+
+    #[test]
+    fn report_truncated() {
+        // The header reports more registrants than the data holds.
+        let b = report_hdr(ReservationReport::data_size(1, false), 0, 4);
+
+        let r = ReservationReport::parse(&b, false);
+        assert_eq!(r.rtype, None);
+        assert_eq!(r.registrants.len(), 1);
+        assert!(r.holder().is_none());
+    }
+
+    #[test]
+    fn reservation_type_round_trip() {
+        for t in [
+            ReservationType::WriteExclusive,
+            ReservationType::ExclusiveAccess,
+            ReservationType::WriteExclusiveRegOnly,
+            ReservationType::ExclusiveAccessRegOnly,
+            ReservationType::WriteExclusiveAllRegs,
+            ReservationType::ExclusiveAccessAllRegs,
+        ] {
+            assert_eq!(ReservationType::from_raw(u8::from(t)), Some(t));
+        }
+
+        assert_eq!(ReservationType::from_raw(0), None);
+        assert_eq!(ReservationType::from_raw(0x7), None);
+    }
+}
@@ -0,0 +1,154 @@
+///! Spin time accounting of pollers.
+use crate::libspdk::{spdk_get_ticks, spdk_get_ticks_hz};
+
+/// Tracks the time a poller spends spinning, i.e. polling its backend
+/// without finding any work, in the same way SPDK `bdev_nvme` does.
+/// A stretch of idle polls counts as spin time once it is followed by a
+/// busy poll; a stretch that is still idle is not counted yet.
+///
+/// A Bdev keeps one tracker per poller in its channel data, calls
+/// `poll_done()` at the end of each poll, and reports the accumulated time
+/// from `BdevOps::spin_time`.
+#[derive(Debug, Default, Clone)]
+pub struct SpinTracker {
+    /// Accumulated spin time, in ticks.
+    spin_ticks: u64,
+    /// Tick count when the current stretch of idle polls started,
+    /// or zero if the last poll was busy.
+    start_ticks: u64,
+    /// Number of busy polls.
+    busy_polls: u64,
+    /// Number of idle polls.
+    idle_polls: u64,
+}
+
+impl SpinTracker {
+    /// Creates a new `SpinTracker`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the result of a poll.
+    ///
+    /// # Arguments
+    ///
+    /// * `busy`: True if the poll has found work to do.
+    pub fn poll_done(&mut self, busy: bool) {
+        self.poll_done_at(busy, || unsafe { spdk_get_ticks() });
+    }
+
+    /// Records the result of a poll, reading the current tick count only
+    /// when a stretch of idle polls starts or ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `busy`: True if the poll has found work to do.
+    /// * `now`: Returns the current tick count.
+    fn poll_done_at(&mut self, busy: bool, now: impl FnOnce() -> u64) {
+        if busy {
+            self.busy_polls += 1;
+            if self.start_ticks != 0 {
+                self.spin_ticks += now().saturating_sub(self.start_ticks);
+                self.start_ticks = 0;
+            }
+        } else {
+            self.idle_polls += 1;
+            if self.start_ticks == 0 {
+                self.start_ticks = now();
+            }
+        }
+    }
+
+    /// Returns the number of busy polls.
+    pub fn busy_polls(&self) -> u64 {
+        self.busy_polls
+    }
+
+    /// Returns the number of idle polls.
+    pub fn idle_polls(&self) -> u64 {
+        self.idle_polls
+    }
+
+    /// Returns the accumulated spin time in ticks, without resetting it.
+    pub fn spin_ticks(&self) -> u64 {
+        self.spin_ticks
+    }
+
+    /// Returns the accumulated spin time in microseconds and resets it,
+    /// as expected by `BdevOps::spin_time`.
+    pub fn take_spin_time_us(&mut self) -> u64 {
+        let ticks = std::mem::take(&mut self.spin_ticks);
+        ticks_to_us(ticks, unsafe { spdk_get_ticks_hz() })
+    }
+}
+
+/// Converts a tick count into microseconds.
+///
+/// # Arguments
+///
+/// * `ticks`: Tick count.
+/// * `hz`: Number of ticks per second.
+fn ticks_to_us(ticks: u64, hz: u64) -> u64 {
+    if hz == 0 {
+        return 0;
+    }
+    (ticks as u128 * 1_000_000 / hz as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records a poll at the given tick count.
+    fn poll(t: &mut SpinTracker, busy: bool, ticks: u64) {
+        t.poll_done_at(busy, || ticks);
+    }
+
+    #[test]
+    fn counts_polls() {
+        let mut t = SpinTracker::new();
+        poll(&mut t, true, 10);
+        poll(&mut t, false, 20);
+        poll(&mut t, false, 30);
+        assert_eq!(t.busy_polls(), 1);
+        assert_eq!(t.idle_polls(), 2);
+    }
+
+    #[test]
+    fn spin_ends_with_busy_poll() {
+        let mut t = SpinTracker::new();
+        poll(&mut t, true, 100);
+        poll(&mut t, false, 200);
+        poll(&mut t, false, 250);
+        poll(&mut t, false, 300);
+
+        // The stretch of idle polls is still going on.
+        assert_eq!(t.spin_ticks(), 0);
+
+        poll(&mut t, true, 350);
+        assert_eq!(t.spin_ticks(), 150);
+
+        poll(&mut t, true, 400);
+        poll(&mut t, false, 500);
+        poll(&mut t, true, 520);
+        assert_eq!(t.spin_ticks(), 170);
+    }
+
+    #[test]
+    fn reads_ticks_only_on_transitions() {
+        let mut t = SpinTracker::new();
+        t.poll_done_at(true, || panic!("no idle stretch to end"));
+        poll(&mut t, false, 10);
+        t.poll_done_at(false, || panic!("idle stretch already started"));
+        poll(&mut t, true, 40);
+        assert_eq!(t.spin_ticks(), 30);
+    }
+
+    #[test]
+    fn ticks_conversion() {
+        assert_eq!(ticks_to_us(3_000, 1_000_000_000), 3);
+        assert_eq!(ticks_to_us(5, 1_000), 5_000);
+        assert_eq!(ticks_to_us(u64::MAX, u64::MAX), 1_000_000);
+        assert_eq!(ticks_to_us(100, 0), 0);
+    }
+}
@@ -1,10 +1,68 @@
 ///! Definition of untyped Bdev alias and related types.
-use crate::{Bdev, BdevIo, BdevOps, IoChannel, IoDevice, IoType};
-use std::pin::Pin;
+use crate::{
+    bdev_module::module_has_bdev_data_type,
+    Bdev,
+    BdevDesc,
+    BdevIo,
+    BdevOps,
+    IoChannel,
+    IoDevice,
+    IoType,
+};
+use std::{any::TypeId, pin::Pin};
 
 /// An alias for a Bdev whose type is unknown or not important.
 pub type UntypedBdev = Bdev<()>;
 
+impl UntypedBdev {
+    /// Converts this Bdev into a typed Bdev, giving access to its data.
+    /// Returns `None` unless the Bdev has been created by `BdevBuilder` with
+    /// the data type `T` recorded (see `BdevBuilder::with_data_type()`), on
+    /// behalf of the module registered via `BdevModuleBuilder` that owns it.
+    pub fn try_downcast<T>(&self) -> Option<Bdev<T>>
+    where
+        T: BdevOps + 'static,
+    {
+        let b = self.as_inner_ref();
+
+        if module_has_bdev_data_type(b.module, TypeId::of::<T>())
+            && self.has_data_type::<T>()
+        {
+            Some(Bdev::from_inner_ptr(self.as_inner_ptr()))
+        } else {
+            None
+        }
+    }
+}
+
+impl BdevDesc<()> {
+    /// Converts this descriptor into a descriptor of a typed Bdev.
+    /// Returns `None` if the Bdev cannot be converted
+    /// (see `UntypedBdev::try_downcast()`).
+    pub fn try_downcast<T>(&self) -> Option<BdevDesc<T>>
+    where
+        T: BdevOps + 'static,
+    {
+        self.bdev()
+            .try_downcast::<T>()
+            .map(|_| BdevDesc::from_ptr(self.as_ptr()))
+    }
+}
+
+impl BdevIo<()> {
+    /// Converts this I/O into an I/O of a typed Bdev.
+    /// Returns `None` if the Bdev cannot be converted
+    /// (see `UntypedBdev::try_downcast()`).
+    pub fn try_downcast<T>(&self) -> Option<BdevIo<T>>
+    where
+        T: BdevOps + 'static,
+    {
+        self.bdev()
+            .try_downcast::<T>()
+            .map(|_| BdevIo::from_ptr(self.as_ptr()))
+    }
+}
+
 /// Dummy implementation of `BdevOps` for an untyped Bdev.
 /// This implementation is provided only to satisfy generics restrictions.
 impl BdevOps for () {
@@ -0,0 +1,283 @@
+///! Zero-copy (ZCOPY) I/O support.
+use std::{os::raw::c_void, ptr::null_mut};
+
+use futures::channel::oneshot;
+use nix::errno::Errno;
+
+use crate::{
+    bdev_desc_io::IoCompletion,
+    ffihelper::{cb_arg, drop_cb_arg},
+    libspdk::{
+        iovec,
+        spdk_bdev_free_io,
+        spdk_bdev_io,
+        spdk_bdev_io_get_iovec,
+        spdk_bdev_io_set_buf,
+        spdk_bdev_zcopy_end,
+        spdk_bdev_zcopy_start,
+    },
+    BdevDesc,
+    BdevDescError,
+    BdevIo,
+    BdevOps,
+    IoChannel,
+    IoType,
+    IoVec,
+};
+
+/// Phase of a zero-copy I/O, as seen by the bdev that serves it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ZcopyPhase {
+    /// The bdev must lend buffers for the requested blocks.
+    /// If `populate` is true, the buffers must be filled with the data of
+    /// the blocks, e.g. for a read.
+    Start { populate: bool },
+    /// The bdev takes back the buffers lent at start.
+    /// If `commit` is true, the data in the buffers must be written to
+    /// the blocks first.
+    End { commit: bool },
+}
+
+impl<BdevData> BdevIo<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Returns the phase of a zero-copy I/O.
+    /// Must only be called for `IoType::ZeroCopy` I/Os.
+    #[inline]
+    pub fn zcopy_phase(&self) -> ZcopyPhase {
+        let zcopy = unsafe { self.as_ref().u.bdev.__bindgen_anon_1.zcopy };
+
+        if zcopy.start() != 0 {
+            ZcopyPhase::Start {
+                populate: zcopy.populate() != 0,
+            }
+        } else {
+            ZcopyPhase::End {
+                commit: zcopy.commit() != 0,
+            }
+        }
+    }
+
+    /// Lends a buffer owned by the bdev to a zero-copy I/O at start.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf`: Buffer of at least `num_blocks() * block_len()` bytes.
+    /// * `len`: Buffer length in bytes.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must point to at least `len` bytes of memory valid for reads
+    /// and writes. The memory must stay valid, and must not be reused by
+    /// the bdev, until the `ZcopyPhase::End` phase of the same zero-copy
+    /// operation has been submitted and the I/O is completed.
+    pub unsafe fn set_zcopy_buf(&self, buf: *mut c_void, len: u64) {
+        spdk_bdev_io_set_buf(self.as_ptr(), buf, len);
+    }
+}
+
+/// Blocks of a bdev borrowed for zero-copy I/O via `BdevDesc::zcopy_start()`.
+/// The buffers are lent by the bdev until `end()` is called. If dropped
+/// without ending, the buffers are released without committing.
+pub struct ZcopyIo<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Descriptor the I/O was started on.
+    desc: BdevDesc<BdevData>,
+    /// Started bdev I/O, or null once ended.
+    bio: *mut spdk_bdev_io,
+}
+
+impl<BdevData> ZcopyIo<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Returns the buffers lent by the bdev.
+    pub fn iovs(&self) -> &[IoVec] {
+        let (iovs, iovcnt) = self.raw_iovs();
+        unsafe { std::slice::from_raw_parts(iovs as *const IoVec, iovcnt) }
+    }
+
+    /// Returns the buffers lent by the bdev, for filling them before
+    /// a commit.
+    pub fn iovs_mut(&mut self) -> &mut [IoVec] {
+        let (iovs, iovcnt) = self.raw_iovs();
+        unsafe { std::slice::from_raw_parts_mut(iovs as *mut IoVec, iovcnt) }
+    }
+
+    /// Returns the raw I/O vector of the lent buffers.
+    fn raw_iovs(&self) -> (*mut iovec, usize) {
+        let mut iovs: *mut iovec = null_mut();
+        let mut iovcnt: i32 = 0;
+
+        unsafe {
+            spdk_bdev_io_get_iovec(self.bio, &mut iovs, &mut iovcnt);
+        }
+
+        (iovs, iovcnt as usize)
+    }
+
+    /// Ends the zero-copy I/O and gives the buffers back to the bdev.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit`: Write the data in the buffers to the blocks.
+    pub async fn end(mut self, commit: bool) -> Result<(), BdevDescError> {
+        let bio = self.bio;
+        let bio_ref = &mut self.bio;
+
+        self.desc
+            .submit_io(IoType::ZeroCopy, |cb, arg| unsafe {
+                let rc = spdk_bdev_zcopy_end(bio, commit, cb, arg);
+                // Once submitted, the I/O is freed by the completion
+                // callback. Otherwise, it is ended by `drop()`.
+                if rc == 0 {
+                    *bio_ref = null_mut();
+                }
+                rc
+            })
+            .await
+            .map(|_| ())
+    }
+}
+
+impl<BdevData> Drop for ZcopyIo<BdevData>
+where
+    BdevData: BdevOps,
+{
+    fn drop(&mut self) {
+        if self.bio.is_null() {
+            return;
+        }
+
+        let rc = unsafe {
+            spdk_bdev_zcopy_end(
+                self.bio,
+                false,
+                Some(inner_zcopy_release_cb),
+                null_mut(),
+            )
+        };
+
+        if rc != 0 {
+            error!(
+                "{}: failed to release zero-copy buffers: {}",
+                self.desc.bdev().name(),
+                Errno::from_i32(rc.abs())
+            );
+            unsafe { spdk_bdev_free_io(self.bio) };
+        }
+    }
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Starts a zero-copy I/O: borrows the buffers of the given blocks from
+    /// the bdev, which must support `IoType::ZeroCopy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `offset_blocks`: Offset in blocks.
+    /// * `num_blocks`: Number of blocks.
+    /// * `populate`: Fill the buffers with the data of the blocks, e.g. for a
+    ///   read.
+    pub async fn zcopy_start(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        offset_blocks: u64,
+        num_blocks: u64,
+        populate: bool,
+    ) -> Result<ZcopyIo<BdevData>, BdevDescError> {
+        let (s, r) = oneshot::channel::<(IoCompletion, *mut spdk_bdev_io)>();
+        let arg = cb_arg(s);
+
+        let rc = unsafe {
+            spdk_bdev_zcopy_start(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                null_mut(),
+                0,
+                offset_blocks,
+                num_blocks,
+                populate,
+                Some(inner_zcopy_start_cb),
+                arg,
+            )
+        };
+
+        if rc != 0 {
+            drop_cb_arg::<(IoCompletion, *mut spdk_bdev_io)>(arg);
+            return Err(BdevDescError::IoSubmit {
+                source: Errno::from_i32(rc.abs()),
+                io_type: IoType::ZeroCopy,
+                bdev_name: self.bdev().name().to_owned(),
+            });
+        }
+
+        let (cpl, bio) = r.await.expect("I/O completion sender is gone");
+        self.check_completion(IoType::ZeroCopy, cpl)?;
+
+        Ok(ZcopyIo {
+            desc: self.clone(),
+            bio,
+        })
+    }
+}
+
+/// Completion callback for `BdevDesc::zcopy_start()`.
+/// A successfully started I/O is kept until it ends; a failed one is freed.
+///
+/// # Arguments
+///
+/// * `bio`: Completed bdev I/O.
+/// * `success`: True if the I/O has started.
+/// * `arg`: Sender created by `cb_arg`.
+unsafe extern "C" fn inner_zcopy_start_cb(
+    bio: *mut spdk_bdev_io,
+    success: bool,
+    arg: *mut c_void,
+) {
+    let s = Box::from_raw(
+        arg as *mut oneshot::Sender<(IoCompletion, *mut spdk_bdev_io)>,
+    );
+    let cpl = IoCompletion::from_bdev_io(bio, success);
+
+    if !success {
+        spdk_bdev_free_io(bio);
+        s.send((cpl, null_mut())).ok();
+        return;
+    }
+
+    // If the waiting future has been dropped, release the buffers.
+    if let Err((_, bio)) = s.send((cpl, bio)) {
+        if spdk_bdev_zcopy_end(
+            bio,
+            false,
+            Some(inner_zcopy_release_cb),
+            null_mut(),
+        ) != 0
+        {
+            error!("Failed to release zero-copy buffers");
+        }
+    }
+}
+
+/// Completion callback for zero-copy I/Os ended without a commit on drop.
+///
+/// # Arguments
+///
+/// * `bio`: Completed bdev I/O.
+/// * `_success`: True if the I/O has succeeded.
+/// * `_arg`: Unused.
+unsafe extern "C" fn inner_zcopy_release_cb(
+    bio: *mut spdk_bdev_io,
+    _success: bool,
+    _arg: *mut c_void,
+) {
+    spdk_bdev_free_io(bio);
+}
@@ -0,0 +1,321 @@
+///! Zoned block device support.
+use std::{convert::TryFrom, mem::zeroed};
+
+use crate::{
+    libspdk::{
+        spdk_bdev_get_zone_info,
+        spdk_bdev_zone_action,
+        spdk_bdev_zone_appendv,
+        spdk_bdev_zone_info,
+        spdk_bdev_zone_management,
+        spdk_bdev_zone_state,
+        SPDK_BDEV_ZONE_CLOSE,
+        SPDK_BDEV_ZONE_FINISH,
+        SPDK_BDEV_ZONE_OFFLINE,
+        SPDK_BDEV_ZONE_OPEN,
+        SPDK_BDEV_ZONE_RESET,
+        SPDK_BDEV_ZONE_STATE_CLOSED,
+        SPDK_BDEV_ZONE_STATE_EMPTY,
+        SPDK_BDEV_ZONE_STATE_EXP_OPEN,
+        SPDK_BDEV_ZONE_STATE_FULL,
+        SPDK_BDEV_ZONE_STATE_IMP_OPEN,
+        SPDK_BDEV_ZONE_STATE_OFFLINE,
+        SPDK_BDEV_ZONE_STATE_READ_ONLY,
+    },
+    AsIoVecPtr,
+    BdevDesc,
+    BdevDescError,
+    BdevIo,
+    BdevOps,
+    IoChannel,
+    IoType,
+    IoVec,
+};
+
+/// Zone management action.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ZoneAction {
+    Close,
+    Finish,
+    Open,
+    Reset,
+    Offline,
+}
+
+impl From<ZoneAction> for spdk_bdev_zone_action {
+    fn from(a: ZoneAction) -> Self {
+        match a {
+            ZoneAction::Close => SPDK_BDEV_ZONE_CLOSE,
+            ZoneAction::Finish => SPDK_BDEV_ZONE_FINISH,
+            ZoneAction::Open => SPDK_BDEV_ZONE_OPEN,
+            ZoneAction::Reset => SPDK_BDEV_ZONE_RESET,
+            ZoneAction::Offline => SPDK_BDEV_ZONE_OFFLINE,
+        }
+    }
+}
+
+/// Converts an SPDK zone action. Fails with the given value if the action
+/// is unknown.
+impl TryFrom<spdk_bdev_zone_action> for ZoneAction {
+    type Error = spdk_bdev_zone_action;
+
+    fn try_from(a: spdk_bdev_zone_action) -> Result<Self, Self::Error> {
+        match a {
+            SPDK_BDEV_ZONE_CLOSE => Ok(ZoneAction::Close),
+            SPDK_BDEV_ZONE_FINISH => Ok(ZoneAction::Finish),
+            SPDK_BDEV_ZONE_OPEN => Ok(ZoneAction::Open),
+            SPDK_BDEV_ZONE_RESET => Ok(ZoneAction::Reset),
+            SPDK_BDEV_ZONE_OFFLINE => Ok(ZoneAction::Offline),
+            _ => Err(a),
+        }
+    }
+}
+
+/// Zone state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ZoneState {
+    Empty,
+    ImplicitOpen,
+    ExplicitOpen,
+    Closed,
+    Full,
+    ReadOnly,
+    Offline,
+}
+
+impl From<ZoneState> for spdk_bdev_zone_state {
+    fn from(s: ZoneState) -> Self {
+        match s {
+            ZoneState::Empty => SPDK_BDEV_ZONE_STATE_EMPTY,
+            ZoneState::ImplicitOpen => SPDK_BDEV_ZONE_STATE_IMP_OPEN,
+            ZoneState::ExplicitOpen => SPDK_BDEV_ZONE_STATE_EXP_OPEN,
+            ZoneState::Closed => SPDK_BDEV_ZONE_STATE_CLOSED,
+            ZoneState::Full => SPDK_BDEV_ZONE_STATE_FULL,
+            ZoneState::ReadOnly => SPDK_BDEV_ZONE_STATE_READ_ONLY,
+            ZoneState::Offline => SPDK_BDEV_ZONE_STATE_OFFLINE,
+        }
+    }
+}
+
+impl From<spdk_bdev_zone_state> for ZoneState {
+    fn from(s: spdk_bdev_zone_state) -> Self {
+        match s {
+            SPDK_BDEV_ZONE_STATE_EMPTY => ZoneState::Empty,
+            SPDK_BDEV_ZONE_STATE_IMP_OPEN => ZoneState::ImplicitOpen,
+            SPDK_BDEV_ZONE_STATE_EXP_OPEN => ZoneState::ExplicitOpen,
+            SPDK_BDEV_ZONE_STATE_CLOSED => ZoneState::Closed,
+            SPDK_BDEV_ZONE_STATE_FULL => ZoneState::Full,
+            SPDK_BDEV_ZONE_STATE_READ_ONLY => ZoneState::ReadOnly,
+            _ => ZoneState::Offline,
+        }
+    }
+}
+
+/// Information about a zone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ZoneInfo {
+    /// Zone identifier: the first block of the zone.
+    pub zone_id: u64,
+    /// Write pointer, in blocks.
+    pub write_pointer: u64,
+    /// Number of writable blocks in the zone.
+    pub capacity: u64,
+    /// Zone state.
+    pub state: ZoneState,
+}
+
+impl From<&spdk_bdev_zone_info> for ZoneInfo {
+    fn from(i: &spdk_bdev_zone_info) -> Self {
+        Self {
+            zone_id: i.zone_id,
+            write_pointer: i.write_pointer,
+            capacity: i.capacity,
+            state: i.state.into(),
+        }
+    }
+}
+
+impl From<&ZoneInfo> for spdk_bdev_zone_info {
+    fn from(i: &ZoneInfo) -> Self {
+        Self {
+            zone_id: i.zone_id,
+            write_pointer: i.write_pointer,
+            capacity: i.capacity,
+            state: i.state.into(),
+        }
+    }
+}
+
+impl<BdevData> BdevIo<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Returns the zone identifier of a zone I/O.
+    #[inline]
+    pub fn zone_id(&self) -> u64 {
+        unsafe {
+            match self.io_type() {
+                // Zone append reuses block I/O parameters.
+                IoType::ZoneAppend => self.as_ref().u.bdev.offset_blocks,
+                _ => self.as_ref().u.zone_mgmt.zone_id,
+            }
+        }
+    }
+
+    /// Returns the number of zones of a zone info I/O.
+    #[inline]
+    pub fn num_zones(&self) -> u32 {
+        unsafe { self.as_ref().u.zone_mgmt.num_zones }
+    }
+
+    /// Returns the action of a zone management I/O, or `None` if the action
+    /// is unknown.
+    #[inline]
+    pub fn zone_action(&self) -> Option<ZoneAction> {
+        ZoneAction::try_from(unsafe { self.as_ref().u.zone_mgmt.zone_action })
+            .ok()
+    }
+
+    /// Fills the zone info buffer of a zone info I/O.
+    /// At most `num_zones()` entries are written.
+    ///
+    /// # Arguments
+    ///
+    /// * `zones`: Information about consecutive zones, starting from
+    ///   `zone_id()`.
+    pub fn set_zone_info(&self, zones: &[ZoneInfo]) {
+        let n = zones.len().min(self.num_zones() as usize);
+        unsafe {
+            let buf = std::slice::from_raw_parts_mut(
+                self.as_ref().u.zone_mgmt.buf as *mut spdk_bdev_zone_info,
+                n,
+            );
+            for (dst, src) in buf.iter_mut().zip(zones) {
+                *dst = src.into();
+            }
+        }
+    }
+
+    /// Sets the starting block of the data written by a zone append I/O.
+    /// Must be called before completing the I/O.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset_blocks`: Block where the data has been written.
+    #[inline]
+    pub fn set_append_location(&self, offset_blocks: u64) {
+        unsafe {
+            (*self.as_ptr()).u.bdev.offset_blocks = offset_blocks;
+        }
+    }
+}
+
+impl<BdevData> BdevDesc<BdevData>
+where
+    BdevData: BdevOps,
+{
+    /// Gets information about consecutive zones.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `zone_id`: First zone.
+    /// * `num_zones`: Number of zones.
+    pub async fn get_zone_info(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        zone_id: u64,
+        num_zones: usize,
+    ) -> Result<Vec<ZoneInfo>, BdevDescError> {
+        // The buffer is owned by the I/O until it completes.
+        let info: Vec<spdk_bdev_zone_info> =
+            vec![unsafe { zeroed() }; num_zones];
+
+        let (_, info) = self
+            .submit_io_with(IoType::ZoneInfo, info, |info, cb, arg| unsafe {
+                spdk_bdev_get_zone_info(
+                    self.as_ptr(),
+                    chan.legacy_as_ptr(),
+                    zone_id,
+                    num_zones as _,
+                    info.as_mut_ptr(),
+                    cb,
+                    arg,
+                )
+            })
+            .await?;
+
+        Ok(info.iter().map(ZoneInfo::from).collect())
+    }
+
+    /// Performs a zone management action.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `zone_id`: Zone.
+    /// * `action`: Action to perform.
+    pub async fn zone_management(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        zone_id: u64,
+        action: ZoneAction,
+    ) -> Result<(), BdevDescError> {
+        self.submit_io(IoType::ZoneManagement, |cb, arg| unsafe {
+            spdk_bdev_zone_management(
+                self.as_ptr(),
+                chan.legacy_as_ptr(),
+                zone_id,
+                action.into(),
+                cb,
+                arg,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Appends data to a zone from an I/O vector.
+    /// Returns the starting block where the data has been written.
+    ///
+    /// # Safety
+    ///
+    /// The memory the I/O vector points to must stay valid until the I/O
+    /// completes: the returned future must be polled to completion, and
+    /// must not be dropped before.
+    ///
+    /// # Arguments
+    ///
+    /// * `chan`: I/O channel of the current thread.
+    /// * `iovs`: I/O vector to write.
+    /// * `zone_id`: Zone.
+    /// * `num_blocks`: Number of blocks to write.
+    pub async unsafe fn zone_append(
+        &self,
+        chan: &IoChannel<BdevData::ChannelData>,
+        iovs: &[IoVec],
+        zone_id: u64,
+        num_blocks: u64,
+    ) -> Result<u64, BdevDescError> {
+        // The I/O vector itself is copied, so that it lives as long as
+        // the I/O.
+        self.submit_io_with(
+            IoType::ZoneAppend,
+            iovs.to_vec(),
+            |iovs, cb, arg| {
+                spdk_bdev_zone_appendv(
+                    self.as_ptr(),
+                    chan.legacy_as_ptr(),
+                    iovs.as_io_vec_mut_ptr(),
+                    iovs.len() as i32,
+                    zone_id,
+                    num_blocks,
+                    cb,
+                    arg,
+                )
+            },
+        )
+        .await
+        .map(|(cpl, _)| cpl.append_location)
+    }
+}
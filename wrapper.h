@@ -16,6 +16,7 @@
 #include <nbd/nbd_internal.h>
 #include <spdk/bdev.h>
 #include <spdk/bdev_module.h>
+#include <spdk/bdev_zone.h>
 #include <spdk/conf.h>
 #include <spdk/cpuset.h>
 #include <spdk/crc32.h>